@@ -1,3 +1,4 @@
+use hal::can::CanError;
 use std::error::Error;
 use std::fmt;
 
@@ -45,6 +46,12 @@ pub enum HalError {
     /// Tried to use a channel that could not be used for whatever type of device this was returned from
     InvalidChannel(i32),
     InvalidModule(i32),
+    /// A bus address was out of range, like an I2C address that doesn't fit in 7 bits
+    InvalidAddress(i32),
+    /// A status code specific to the CAN session mux
+    Can(CanError),
+    /// A bus transfer was aborted, usually because no device acknowledged it
+    TransferAborted,
     /// Some other custom error
     Other(Box<Error + Send + Sync>),
 }
@@ -55,6 +62,9 @@ impl fmt::Display for HalError {
             HalError::Hal(ref ffi_err) => ffi_err.description(),
             HalError::InvalidChannel(_) => "Invalid channel",
             HalError::InvalidModule(_) => "Invalid module",
+            HalError::InvalidAddress(_) => "Invalid address",
+            HalError::Can(ref can_err) => can_err.message(),
+            HalError::TransferAborted => "Transfer aborted",
             HalError::Other(ref err) => err.description(),
         };
 
@@ -1,6 +1,8 @@
 use hal::types::{AnalogInputHandle, AnalogTriggerHandle, NativeBool};
 use error::*;
 use hal::analog::AnalogInput;
+use hal::dio::DigitalSource;
+use hal::types::Handle;
 use std::ops::Range;
 use std::os::raw::*;
 
@@ -71,6 +73,32 @@ impl<'i> AnalogTrigger<'i> {
     pub fn get_trigger_output(&self, trigger_type: AnalogTriggerType) -> HalResult<bool> {
         unsafe { hal_call!(HAL_GetAnalogTriggerOutput(self.port, trigger_type)).map(|n| n!= 0) }
     }
+
+    /// Get one of the outputs of this trigger, which can be used as a `DigitalSource`.
+    pub fn output<'t>(&'t self, output_type: AnalogTriggerType) -> AnalogTriggerOutput<'t, 'i> {
+        AnalogTriggerOutput { trigger: self, output_type }
+    }
+}
+
+/// A single output of an analog trigger, usable anywhere a `DigitalSource` is.
+#[derive(Debug)]
+pub struct AnalogTriggerOutput<'t, 'i: 't> {
+    trigger: &'t AnalogTrigger<'i>,
+    output_type: AnalogTriggerType,
+}
+
+impl<'t, 'i> AnalogTriggerOutput<'t, 'i> {
+    pub fn get(&self) -> HalResult<bool> {
+        self.trigger.get_trigger_output(self.output_type)
+    }
+
+    pub fn output_type(&self) -> AnalogTriggerType { self.output_type }
+}
+
+impl<'t, 'i> DigitalSource for AnalogTriggerOutput<'t, 'i> {
+    fn handle(&self) -> Handle { self.trigger.port }
+
+    fn analog_trigger_type(&self) -> AnalogTriggerType { self.output_type }
 }
 
 impl<'i> Drop for AnalogTrigger<'i> {
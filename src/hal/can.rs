@@ -1,27 +1,405 @@
 use error::*;
+use hal::time::CanTimestamp;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
 use std::os::raw::c_float;
+use std::ptr;
+use std::time::{Duration, Instant};
 
 extern "C" {
-	fn FRC_NetworkCommunication_CANSessionMux_sendMessage(message_id: u32, data: *const u8, data_size: u8, period_ms: i32, status: *mut i32);
-	fn FRC_NetworkCommunication_CANSessionMux_receiveMessage(message_id: u32, message_id_mask: u32, data: *mut u8, data_size: *mut u8, timestamp: *mut u32, status: *mut i32);
+    fn FRC_NetworkCommunication_CANSessionMux_sendMessage(message_id: u32, data: *const u8, data_size: u8, period_ms: i32, status: *mut i32);
+    fn FRC_NetworkCommunication_CANSessionMux_receiveMessage(message_id: *mut u32, message_id_mask: u32, data: *mut u8, data_size: *mut u8, timestamp: *mut u32, status: *mut i32);
     fn FRC_NetworkCommunication_CANSessionMux_openStreamSession(session_handle: *mut u32, message_id: u32, message_id_mask: u32, max_messages: u32, status: *mut i32);
-	fn FRC_NetworkCommunication_CANSessionMux_closeStreamSession(session_handle: u32);
-	fn FRC_NetworkCommunication_CANSessionMux_readStreamSession(session_handle: u32, messages: *mut CanStreamMessage, messages_to_read: u32, messages_read: *mut u32, status: *mut i32);
-	fn FRC_NetworkCommunication_CANSessionMux_getCANStatus(percent_bus_utilization: *mut c_float, bus_off_count: *mut u32, tx_full_count: *mut u32, receive_error_count: *mut u32, transmit_error_count: *mut u32, status: *mut i32);
+    fn FRC_NetworkCommunication_CANSessionMux_closeStreamSession(session_handle: u32);
+    fn FRC_NetworkCommunication_CANSessionMux_readStreamSession(session_handle: u32, messages: *mut CanStreamMessage, messages_to_read: u32, messages_read: *mut u32, status: *mut i32);
+    fn FRC_NetworkCommunication_CANSessionMux_getCANStatus(percent_bus_utilization: *mut c_float, bus_off_count: *mut u32, tx_full_count: *mut u32, receive_error_count: *mut u32, transmit_error_count: *mut u32, status: *mut i32);
 }
 
+/// Set on a message id to mark the frame as a remote frame.
+pub const CAN_IS_FRAME_REMOTE: u32 = 0x8000_0000;
+/// Set on a message id to mark the frame as using an 11 bit (standard) identifier.
+pub const CAN_IS_FRAME_11BIT: u32 = 0x4000_0000;
+
+/// The maximum amount of data bytes a single CAN frame can carry.
+pub const MAX_MESSAGE_SIZE: usize = 8;
+
+const CAN_SEND_PERIOD_NO_REPEAT: i32 = 0;
+const CAN_SEND_PERIOD_STOP_REPEATING: i32 = -1;
+
+const ERR_INVALID_BUFFER: i32 = -44086;
+const ERR_MESSAGE_NOT_FOUND: i32 = -44087;
+const WARN_NO_TOKEN: i32 = 44087;
+const ERR_NOT_ALLOWED: i32 = -44088;
+const ERR_NOT_INITIALIZED: i32 = -44089;
+const ERR_SESSION_OVERRUN: i32 = 44050;
+
+/// Status codes specific to the CAN session mux.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CanError {
+    /// The data buffer was invalid, usually because it was longer than 8 bytes.
+    InvalidBuffer,
+    /// No message matching the requested id and mask has been received.
+    MessageNotFound,
+    /// The session mux couldn't get a transmit token, so the message wasn't sent. This is a
+    /// warning, and retrying later usually succeeds.
+    NoToken,
+    /// The message id is reserved and may not be used by user code.
+    NotAllowed,
+    /// The CAN session mux has not been initialized yet.
+    NotInitialized,
+    /// A stream session's queue filled up and messages were dropped.
+    SessionOverrun,
+}
+
+impl CanError {
+    fn from_status(status: i32) -> Option<Self> {
+        match status {
+            ERR_INVALID_BUFFER => Some(CanError::InvalidBuffer),
+            ERR_MESSAGE_NOT_FOUND => Some(CanError::MessageNotFound),
+            WARN_NO_TOKEN => Some(CanError::NoToken),
+            ERR_NOT_ALLOWED => Some(CanError::NotAllowed),
+            ERR_NOT_INITIALIZED => Some(CanError::NotInitialized),
+            ERR_SESSION_OVERRUN => Some(CanError::SessionOverrun),
+            _ => None,
+        }
+    }
+}
+
+impl CanError {
+    pub(crate) fn message(&self) -> &'static str {
+        match *self {
+            CanError::InvalidBuffer => "CAN: Invalid buffer",
+            CanError::MessageNotFound => "CAN: Message not found",
+            CanError::NoToken => "CAN: No token",
+            CanError::NotAllowed => "CAN: Not allowed",
+            CanError::NotInitialized => "CAN: Not initialized",
+            CanError::SessionOverrun => "CAN: Session overrun",
+        }
+    }
+}
+
+impl fmt::Display for CanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for CanError {
+    fn description(&self) -> &str { self.message() }
+}
+
+/// Turn a CAN session mux status code into a `HalResult`, mapping the mux specific codes to
+/// `HalError::Can`.
+fn check_status(status: i32) -> HalResult<()> {
+    match status {
+        0 => Ok(()),
+        status => Err(match CanError::from_status(status) {
+            Some(err) => HalError::Can(err),
+            None => HalError::Hal(FfiError(status)),
+        }),
+    }
+}
+
+/// Like `hal_call!`, but maps CAN specific status codes to `HalError::Can`
+macro_rules! can_call {
+    ($function:ident($($arg:expr),*)) => {{
+        let mut status = 0;
+        let result = $function($($arg,)* &mut status as *mut i32);
+        check_status(status).map(|_| result)
+    }};
+}
+
+/// A single CAN frame, as received from the session mux.
 #[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Default)]
 pub struct CanStreamMessage {
-	pub message_id: u32,
-	pub timestamp: u32,
-	pub data: [u8; 8],
-	pub data_size: u8,
+    pub message_id: u32,
+    /// Time the message was received, in milliseconds of the monotonic clock rather than FPGA
+    /// time. See `received_at`.
+    pub timestamp: u32,
+    pub data: [u8; MAX_MESSAGE_SIZE],
+    pub data_size: u8,
+}
+
+impl CanStreamMessage {
+    /// When the message was received.
+    pub fn received_at(&self) -> CanTimestamp { CanTimestamp::from_millis(self.timestamp) }
+
+    /// The valid portion of the data buffer.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..::std::cmp::min(self.data_size as usize, MAX_MESSAGE_SIZE)]
+    }
+}
+
+/// Send a message with the given arbitration id. If `period` is `Some`, the message will be
+/// resent on that period until `stop_periodic_message` is called for the same id, otherwise it
+/// is sent once.
+///
+/// `data` may be at most 8 bytes long; longer buffers return a `CanError::InvalidBuffer`.
+pub fn send_message(id: u32, data: &[u8], period: Option<Duration>) -> HalResult<()> {
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(HalError::Can(CanError::InvalidBuffer));
+    }
+
+    let period_ms = match period {
+        Some(period) => {
+            let millis = period.as_secs()
+                .saturating_mul(1000)
+                .saturating_add(period.subsec_nanos() as u64 / 1_000_000);
+            // A period of zero means "don't repeat", so round up to the smallest real period.
+            ::std::cmp::max(millis, 1).min(i32::MAX as u64) as i32
+        }
+        None => CAN_SEND_PERIOD_NO_REPEAT,
+    };
+
+    unsafe {
+        can_call!(FRC_NetworkCommunication_CANSessionMux_sendMessage(id, data.as_ptr(), data.len() as u8, period_ms))
+    }
 }
 
-pub fn send_message(id: u32, data: &[u8], period_md: i32) -> HalResult<()> {
+/// Stop resending a message that was started with a period by `send_message`.
+pub fn stop_periodic_message(id: u32) -> HalResult<()> {
+    unsafe {
+        can_call!(FRC_NetworkCommunication_CANSessionMux_sendMessage(id, ptr::null(), 0, CAN_SEND_PERIOD_STOP_REPEATING))
+    }
+}
+
+/// Get the most recently received message whose id matches `id` in the bits that are set in
+/// `id_mask`. The returned message's id is the actual id of the frame that matched.
+///
+/// If no such message has been received, `CanError::MessageNotFound` is returned.
+pub fn receive_message(id: u32, id_mask: u32) -> HalResult<CanStreamMessage> {
+    let mut message = CanStreamMessage { message_id: id, ..CanStreamMessage::default() };
+
+    unsafe {
+        can_call!(FRC_NetworkCommunication_CANSessionMux_receiveMessage(&mut message.message_id, id_mask,
+                                                                        message.data.as_mut_ptr(),
+                                                                        &mut message.data_size,
+                                                                        &mut message.timestamp))?;
+    }
+
+    Ok(message)
+}
 
+/// A stream session that queues every message matching an id and mask, so that none are missed
+/// between reads. The session is closed when this is dropped.
+#[derive(Debug)]
+pub struct CanStream {
+    session: u32,
+    max_messages: u32,
+    overrun: Cell<bool>,
 }
 
-pub fn receive_message(id: u32, id_mask: u32) -> HalResult<()> {
+impl CanStream {
+    /// Open a stream session for messages whose id matches `id` in the bits set in `id_mask`.
+    /// `max_messages` is the depth of the queue; once full, new messages are dropped until the
+    /// queue is read.
+    pub fn open(id: u32, id_mask: u32, max_messages: u32) -> HalResult<Self> {
+        let mut session = 0;
+        unsafe {
+            can_call!(FRC_NetworkCommunication_CANSessionMux_openStreamSession(&mut session, id, id_mask, max_messages))?;
+        }
+
+        Ok(CanStream { session, max_messages, overrun: Cell::new(false) })
+    }
+
+    /// The depth of the queue this session was opened with.
+    pub fn max_messages(&self) -> u32 { self.max_messages }
+
+    /// Read as many queued messages as fit into `buffer`, returning how many were read. An
+    /// empty queue is not an error and reads zero messages.
+    pub fn read(&self, buffer: &mut [CanStreamMessage]) -> HalResult<usize> {
+        let mut read = 0;
+        let to_read = ::std::cmp::min(buffer.len(), u32::MAX as usize) as u32;
+        let result = unsafe {
+            can_call!(FRC_NetworkCommunication_CANSessionMux_readStreamSession(self.session, buffer.as_mut_ptr(), to_read, &mut read))
+        };
+
+        match result {
+            Ok(()) => Ok(read as usize),
+            // The messages are still read when the queue overran, we just need to remember that
+            // some were dropped.
+            Err(HalError::Can(CanError::SessionOverrun)) => {
+                self.overrun.set(true);
+                Ok(read as usize)
+            }
+            Err(HalError::Can(CanError::MessageNotFound)) => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns whether the queue has overrun and dropped messages since the last call to this
+    /// function.
+    pub fn take_overrun(&self) -> bool {
+        self.overrun.replace(false)
+    }
+
+    /// Iterate over every message currently queued. The iterator ends once the queue is empty,
+    /// or after yielding an error.
+    pub fn messages<'s>(&'s self) -> CanStreamMessages<'s> {
+        CanStreamMessages {
+            stream: self,
+            buffer: vec![CanStreamMessage::default(); self.max_messages as usize],
+            position: 0,
+            length: 0,
+            done: false,
+        }
+    }
+}
+
+impl Drop for CanStream {
+    fn drop(&mut self) {
+        unsafe { FRC_NetworkCommunication_CANSessionMux_closeStreamSession(self.session); }
+    }
+}
+
+/// Iterator over the queued messages of a `CanStream`, created by `CanStream::messages`.
+#[derive(Debug)]
+pub struct CanStreamMessages<'s> {
+    stream: &'s CanStream,
+    buffer: Vec<CanStreamMessage>,
+    position: usize,
+    length: usize,
+    done: bool,
+}
+
+impl<'s> Iterator for CanStreamMessages<'s> {
+    type Item = HalResult<CanStreamMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.length {
+            if self.done || self.buffer.is_empty() {
+                return None;
+            }
+
+            match self.stream.read(&mut self.buffer) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(read) => {
+                    self.position = 0;
+                    self.length = read;
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        let message = self.buffer[self.position];
+        self.position += 1;
+        Some(Ok(message))
+    }
+}
+
+/// A snapshot of the health of the CAN bus.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct CanBusStatus {
+    /// Bus utilization, from 0 to 1.
+    pub percent_bus_utilization: f32,
+    /// The number of times the controller has entered the bus-off state.
+    pub bus_off_count: u32,
+    /// The number of times a transmit was dropped because the transmit queue was full.
+    pub tx_full_count: u32,
+    /// The controller's receive error counter. This counts both up and down.
+    pub receive_error_count: u32,
+    /// The controller's transmit error counter. This counts both up and down.
+    pub transmit_error_count: u32,
+}
+
+pub fn get_can_status() -> HalResult<CanBusStatus> {
+    let mut status = CanBusStatus::default();
+    unsafe {
+        can_call!(FRC_NetworkCommunication_CANSessionMux_getCANStatus(&mut status.percent_bus_utilization,
+                                                                      &mut status.bus_off_count,
+                                                                      &mut status.tx_full_count,
+                                                                      &mut status.receive_error_count,
+                                                                      &mut status.transmit_error_count))?;
+    }
+    Ok(status)
+}
+
+/// The change in bus status between two samples of a `CanBusMonitor`.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct CanBusDelta {
+    /// Time between the two samples.
+    pub elapsed: Duration,
+    pub bus_off_count: u32,
+    pub tx_full_count: u32,
+    /// The error counters are not cumulative, so these can be negative when the bus recovers.
+    pub receive_error_count: i64,
+    pub transmit_error_count: i64,
+}
+
+impl CanBusDelta {
+    /// The number of error events in this delta: new bus-off and TX-full events, plus any
+    /// increase in the receive and transmit error counters.
+    pub fn error_events(&self) -> u64 {
+        self.bus_off_count as u64 + self.tx_full_count as u64
+            + ::std::cmp::max(self.receive_error_count, 0) as u64
+            + ::std::cmp::max(self.transmit_error_count, 0) as u64
+    }
+}
+
+/// Tracks the CAN bus status over time. Call `update` periodically (every robot loop, for
+/// example) to take a new sample.
+#[derive(Debug, Default)]
+pub struct CanBusMonitor {
+    last: Option<(Instant, CanBusStatus)>,
+    last_delta: CanBusDelta,
+    peak_utilization: f32,
+}
+
+impl CanBusMonitor {
+    pub fn new() -> Self { CanBusMonitor::default() }
+
+    /// Sample the bus status, returning the change since the last sample. The first call
+    /// returns an empty delta.
+    pub fn update(&mut self) -> HalResult<CanBusDelta> {
+        let status = get_can_status()?;
+        let now = Instant::now();
+
+        let delta = match self.last {
+            Some((last_time, last)) => CanBusDelta {
+                elapsed: now.duration_since(last_time),
+                bus_off_count: status.bus_off_count.wrapping_sub(last.bus_off_count),
+                tx_full_count: status.tx_full_count.wrapping_sub(last.tx_full_count),
+                receive_error_count: status.receive_error_count as i64 - last.receive_error_count as i64,
+                transmit_error_count: status.transmit_error_count as i64 - last.transmit_error_count as i64,
+            },
+            None => CanBusDelta::default(),
+        };
+
+        if status.percent_bus_utilization > self.peak_utilization {
+            self.peak_utilization = status.percent_bus_utilization;
+        }
+        self.last = Some((now, status));
+        self.last_delta = delta;
+
+        Ok(delta)
+    }
+
+    /// The most recent sample, if `update` has been called.
+    pub fn status(&self) -> Option<CanBusStatus> {
+        self.last.map(|(_, status)| status)
+    }
+
+    /// The change between the two most recent samples.
+    pub fn last_delta(&self) -> CanBusDelta { self.last_delta }
+
+    /// The highest bus utilization seen since creation or the last call to `reset_peak`.
+    pub fn peak_utilization(&self) -> f32 { self.peak_utilization }
+
+    pub fn reset_peak(&mut self) { self.peak_utilization = 0.0; }
 
+    /// Error events per second between the two most recent samples. See
+    /// `CanBusDelta::error_events` for what counts as an error event.
+    pub fn error_rate(&self) -> f64 {
+        let elapsed = self.last_delta.elapsed;
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        if secs == 0.0 { 0.0 } else { self.last_delta.error_events() as f64 / secs }
+    }
 }
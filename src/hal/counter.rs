@@ -1,6 +1,8 @@
 use error::*;
 use hal::analog_trigger::AnalogTriggerType;
-use hal::types::{CounterHandle, Handle, DigitalHandle, NativeBool};
+use hal::dio::{DigitalSource, SourceBorrow};
+use hal::types::{CounterHandle, Handle, NativeBool};
+use std::marker::PhantomData;
 
 extern "C" {
     fn HAL_InitializeCounter(mode: CounterMode, index: *mut i32, status: *mut i32) -> CounterHandle;
@@ -36,3 +38,129 @@ pub enum CounterMode {
     PulseLength = 2,
     ExternalDirection = 3,
 }
+
+/// A hardware counter. Depending on the mode, counts edges on one or two digital sources, or
+/// measures the length of pulses on a source. The sources are borrowed for the lifetime `'s`.
+#[derive(Debug, Eq, PartialEq, Hash)]
+pub struct Counter<'s> {
+    pub(crate) handle: CounterHandle,
+    index: i32,
+    _sources: SourceBorrow<'s>,
+}
+
+impl<'s> Counter<'s> {
+    pub fn new(mode: CounterMode) -> HalResult<Self> {
+        let mut index = 0;
+        let handle = unsafe { hal_call!(HAL_InitializeCounter(mode, &mut index))? };
+        Ok(Counter { handle, index, _sources: PhantomData })
+    }
+
+    /// The index of the FPGA counter this was allocated.
+    pub fn index(&self) -> i32 { self.index }
+
+    pub fn set_average_size(&self, size: i32) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterAverageSize(self.handle, size)) }
+    }
+
+    pub fn set_up_source(&self, source: &'s impl DigitalSource) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterUpSource(self.handle, source.handle(), source.analog_trigger_type())) }
+    }
+
+    /// Set which edges of the up source are counted.
+    pub fn set_up_source_edge(&self, rising: bool, falling: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterUpSourceEdge(self.handle, rising as NativeBool, falling as NativeBool)) }
+    }
+
+    pub fn clear_up_source(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_ClearCounterUpSource(self.handle)) }
+    }
+
+    pub fn set_down_source(&self, source: &'s impl DigitalSource) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterDownSource(self.handle, source.handle(), source.analog_trigger_type())) }
+    }
+
+    /// Set which edges of the down source are counted.
+    pub fn set_down_source_edge(&self, rising: bool, falling: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterDownSourceEdge(self.handle, rising as NativeBool, falling as NativeBool)) }
+    }
+
+    pub fn clear_down_source(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_ClearCounterDownSource(self.handle)) }
+    }
+
+    /// Count up on the up source and down on the down source.
+    pub fn set_up_down_mode(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterUpDownMode(self.handle)) }
+    }
+
+    /// Count edges on the up source, using the down source to decide the direction.
+    pub fn set_external_direction_mode(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterExternalDirectionMode(self.handle)) }
+    }
+
+    /// Measure the time between edges of the up source. If `high_semi_period` is true, the
+    /// time the source is high is measured, otherwise the time it is low.
+    pub fn set_semi_period_mode(&self, high_semi_period: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterSemiPeriodMode(self.handle, high_semi_period as NativeBool)) }
+    }
+
+    /// Count up for pulses shorter than `threshold` seconds, and down for longer pulses.
+    pub fn set_pulse_length_mode(&self, threshold: f64) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterPulseLengthMode(self.handle, threshold)) }
+    }
+
+    pub fn get_samples_to_average(&self) -> HalResult<i32> {
+        unsafe { hal_call!(HAL_GetCounterSamplesToAverage(self.handle)) }
+    }
+
+    /// Set the number of samples averaged when calculating the period. Valid values are
+    /// between 1 and 127.
+    pub fn set_samples_to_average(&self, samples: i32) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterSamplesToAverage(self.handle, samples)) }
+    }
+
+    pub fn reset(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_ResetCounter(self.handle)) }
+    }
+
+    pub fn get(&self) -> HalResult<i32> {
+        unsafe { hal_call!(HAL_GetCounter(self.handle)) }
+    }
+
+    /// Get the period of the most recent count, in seconds.
+    pub fn get_period(&self) -> HalResult<f64> {
+        unsafe { hal_call!(HAL_GetCounterPeriod(self.handle)) }
+    }
+
+    /// Set the period in seconds after which the counter is considered stopped.
+    pub fn set_max_period(&self, max_period: f64) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterMaxPeriod(self.handle, max_period)) }
+    }
+
+    /// Whether the period should be updated when the counter has stopped.
+    pub fn set_update_when_empty(&self, enabled: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterUpdateWhenEmpty(self.handle, enabled as NativeBool)) }
+    }
+
+    /// Whether the last period was longer than the max period.
+    pub fn get_stopped(&self) -> HalResult<bool> {
+        unsafe { hal_call!(HAL_GetCounterStopped(self.handle)).map(|n| n != 0) }
+    }
+
+    /// The direction of the last count.
+    pub fn get_direction(&self) -> HalResult<bool> {
+        unsafe { hal_call!(HAL_GetCounterDirection(self.handle)).map(|n| n != 0) }
+    }
+
+    /// Only has an effect in external direction mode.
+    pub fn set_reverse_direction(&self, reverse: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetCounterReverseDirection(self.handle, reverse as NativeBool)) }
+    }
+}
+
+impl<'s> Drop for Counter<'s> {
+    fn drop(&mut self) {
+        // The status param is never set
+        unsafe { HAL_FreeCounter(self.handle, ::std::ptr::null_mut()); }
+    }
+}
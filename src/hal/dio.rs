@@ -1,5 +1,8 @@
 use error::*;
+use hal::analog_trigger::AnalogTriggerType;
+use hal::motor_safety::{Actuator, StopAction};
 use hal::types::*;
+use std::marker::PhantomData;
 use std::os::raw::*;
 
 extern "C" {
@@ -25,6 +28,38 @@ extern "C" {
     fn HAL_SetDigitalPWMOutputChannel(pwm: DigitalPwmHandle, channel: i32, status: *mut i32);
 }
 
+/// Something that can be routed to FPGA peripherals that take a digital signal, like counters,
+/// encoders and interrupts. Those APIs take a reference to the source and borrow it for as long
+/// as they use it, so a source can't be dropped while the FPGA still reads from it:
+///
+/// ```compile_fail,E0597
+/// # use wpilib_hal::counter::{Counter, CounterMode};
+/// # use wpilib_hal::dio::DigitalInput;
+/// # fn main() -> wpilib_hal::error::HalResult<()> {
+/// let counter = Counter::new(CounterMode::TwoPulse)?;
+/// {
+///     let input = DigitalInput::new(0)?;
+///     counter.set_up_source(&input)?;
+/// }
+/// counter.get()?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait DigitalSource {
+    /// The handle used to route this source. This is not necessarily a `DigitalHandle`; analog
+    /// trigger outputs return the handle of their trigger.
+    fn handle(&self) -> Handle;
+
+    /// Which output of the analog trigger to route. Ignored for sources that aren't analog
+    /// triggers.
+    fn analog_trigger_type(&self) -> AnalogTriggerType { AnalogTriggerType::InWindow }
+}
+
+// Marks a borrow of the digital sources routed to a peripheral. Invariant in `'s`, since the
+// sources are passed to setters that take `&self`; a covariant marker would let
+// `&Counter<'long>` shrink to `&Counter<'short>` and accept a source that dies first.
+pub(crate) type SourceBorrow<'s> = PhantomData<fn(&'s ()) -> &'s ()>;
+
 fn check_digital_channel(channel: i32) -> bool {
     unsafe { HAL_CheckDIOChannel(channel) != 0 }
 }
@@ -68,6 +103,10 @@ impl DigitalInput {
     }
 }
 
+impl DigitalSource for DigitalInput {
+    fn handle(&self) -> Handle { self.dio.handle }
+}
+
 #[derive(Debug)]
 pub struct DigitalOutput {
     dio: DigitalIO
@@ -97,6 +136,10 @@ impl DigitalOutput {
     }
 }
 
+impl DigitalSource for DigitalOutput {
+    fn handle(&self) -> Handle { self.dio.handle }
+}
+
 /// Struct to generate a PWM signal on a digital output rather than controlling on/off manually.
 #[derive(Debug)]
 pub struct PwmGenerator<'out> {
@@ -128,6 +171,14 @@ impl<'out> PwmGenerator<'out> {
     }
 }
 
+fn stop_pwm_generator(handle: Handle) -> HalResult<()> {
+    unsafe { hal_call!(HAL_SetDigitalPWMDutyCycle(handle, 0.0)) }
+}
+
+impl<'out> Actuator for PwmGenerator<'out> {
+    fn stop_action(&self) -> StopAction { StopAction::new(self.pwm_handle, stop_pwm_generator) }
+}
+
 impl<'out> Drop for PwmGenerator<'out> {
     fn drop(&mut self) {
         // Unused status param
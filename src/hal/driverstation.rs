@@ -1,5 +1,7 @@
 use std::os::raw::*;
-use std::ffi::CString;
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::ptr;
 use hal::types::*;
 use error::*;
 
@@ -10,10 +12,10 @@ extern "C" {
                      call_stack: *const c_char,
                      print_msg: NativeBool) -> i32;
     fn HAL_GetControlWord(control_word: *mut u32) -> i32;
-    fn HAL_GetAllianceStation(status: *mut i32) -> AllianceStation;
+    fn HAL_GetAllianceStation(status: *mut i32) -> i32;
     fn HAL_GetMatchTime(status: *mut i32) -> c_double;
-    fn HAL_GetMatchInfo(info: *mut MatchInfo) -> c_int;
-    fn HAL_FreeMatchInfo(info: *mut MatchInfo);
+    fn HAL_GetMatchInfo(info: *mut RawMatchInfo) -> c_int;
+    fn HAL_FreeMatchInfo(info: *mut RawMatchInfo);
 
     fn HAL_ReleaseDSMutex();
     fn HAL_InitializeDriverStation();
@@ -54,6 +56,24 @@ pub enum AllianceStation {
 }
 
 impl AllianceStation {
+    /// Convert a raw `HAL_AllianceStationID`, returning `None` if it is out of range.
+    pub fn from_raw(raw: i32) -> Option<Self> {
+        use self::AllianceStation::*;
+        match raw {
+            0 => Some(Red1),
+            1 => Some(Red2),
+            2 => Some(Red3),
+            3 => Some(Blue1),
+            4 => Some(Blue2),
+            5 => Some(Blue3),
+            _ => None,
+        }
+    }
+
+    pub fn alliance(&self) -> Alliance {
+        if self.is_red() { Alliance::Red } else { Alliance::Blue }
+    }
+
     pub fn is_red(&self) -> bool {
         use self::AllianceStation::*;
         match *self {
@@ -74,7 +94,11 @@ impl AllianceStation {
     }
 }
 
-#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Alliance {
+    Red, Blue,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum MatchType {
     None,
@@ -83,27 +107,68 @@ pub enum MatchType {
     Elimination,
 }
 
+impl MatchType {
+    fn from_raw(raw: i32) -> Self {
+        match raw {
+            1 => MatchType::Practice,
+            2 => MatchType::Qualification,
+            3 => MatchType::Elimination,
+            _ => MatchType::None,
+        }
+    }
+}
+
+// Layout of `HAL_MatchInfo`. The strings are allocated by the HAL and have to be given back
+// with `HAL_FreeMatchInfo`.
 #[repr(C)]
-#[derive(Debug)]
-pub struct MatchInfo {
+struct RawMatchInfo {
     event_name: *mut c_char,
-    match_type: MatchType,
+    match_type: i32,
     match_number: u16,
     replay_number: u8,
     game_specific_message: *mut c_char,
 }
 
-impl Drop for MatchInfo {
-    fn drop(&mut self) {
-        unsafe { HAL_FreeMatchInfo(self); }
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MatchInfo {
+    pub event_name: String,
+    pub match_type: MatchType,
+    pub match_number: u16,
+    pub replay_number: u8,
+    pub game_specific_message: String,
+}
+
+unsafe fn copy_c_string(string: *const c_char) -> String {
+    if string.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(string).to_string_lossy().into_owned()
     }
 }
 
 pub fn get_match_info() -> HalResult<MatchInfo> {
+    let mut raw = RawMatchInfo {
+        event_name: ptr::null_mut(),
+        match_type: 0,
+        match_number: 0,
+        replay_number: 0,
+        game_specific_message: ptr::null_mut(),
+    };
+
     unsafe {
-        let mut match_info = ::std::mem::uninitialized();
-        hal_call!(ret HAL_GetMatchInfo(&mut match_info))?;
-        Ok(match_info)
+        let result = hal_call!(ret HAL_GetMatchInfo(&mut raw));
+        let info = result.map(|_| MatchInfo {
+            event_name: copy_c_string(raw.event_name),
+            match_type: MatchType::from_raw(raw.match_type),
+            match_number: raw.match_number,
+            replay_number: raw.replay_number,
+            game_specific_message: copy_c_string(raw.game_specific_message),
+        });
+
+        // The strings start out null, and freeing null is fine, so this is safe even if the
+        // call failed before filling them in
+        HAL_FreeMatchInfo(&mut raw);
+        info
     }
 }
 
@@ -111,10 +176,27 @@ pub fn match_time() -> HalResult<f64> {
     unsafe { hal_call!(HAL_GetMatchTime()).map(|time| time as f64) }
 }
 
-// what in fresh hell does this function do when the FMS is detached or the DS is offline????
-// pub fn get_alliance_station() -> HalResult<AllianceStation> {
-//     unsafe { hal_call!(HAL_GetAllianceStation()) }
-// }
+/// Get the alliance station the robot is in. The HAL reports whatever the last DS packet held,
+/// so `None` is returned when the driver station isn't attached, as well as when the HAL call
+/// fails or the value is out of range.
+pub fn get_alliance_station() -> Option<AllianceStation> {
+    match get_control_word() {
+        Ok(ref word) if word.ds_attached() => {},
+        _ => return None,
+    }
+
+    unsafe { hal_call!(HAL_GetAllianceStation()) }.ok().and_then(AllianceStation::from_raw)
+}
+
+/// Get the alliance color, or `None` if the alliance station isn't known.
+pub fn get_alliance() -> Option<Alliance> {
+    get_alliance_station().map(|station| station.alliance())
+}
+
+/// Get the driver station number, from 1 to 3, or `None` if the alliance station isn't known.
+pub fn get_station_number() -> Option<usize> {
+    get_alliance_station().map(|station| station.station())
+}
 
 /// Wait for new information from the driver station. `true` is returned when new data is available
 /// and `false` is returned on timeout. `timeout` is the time to wait for new data in seconds.
@@ -128,8 +210,32 @@ pub fn wait_for_data(timeout: Option<f64>) -> bool {
     }
 }
 
+thread_local! {
+    // The HAL's new data flag is per thread and cleared by reading it, so one reader on a thread
+    // would hide packets from every other. Instead the flag is only read by `packets_seen`,
+    // which counts the packets it reports, and each reader compares the count against the
+    // last one it saw.
+    static PACKETS_SEEN: Cell<u64> = const { Cell::new(0) };
+    static LAST_CHECKED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Count of packets this thread has noticed arriving. Packets that arrive between two calls
+/// are counted once, so this is only good for telling whether something new has arrived.
+pub(crate) fn packets_seen() -> u64 {
+    PACKETS_SEEN.with(|seen| {
+        if unsafe { HAL_IsNewControlData() != 0 } {
+            seen.set(seen.get() + 1);
+        }
+        seen.get()
+    })
+}
+
+/// Whether a new packet has arrived since the last call to this function on this thread.
+/// `JoystickState` and `DriverStationSnapshot` keep track of packets separately, so using them
+/// doesn't change what this returns.
 pub fn is_new_control_data() -> bool {
-    unsafe { HAL_IsNewControlData() != 0 }
+    let seen = packets_seen();
+    LAST_CHECKED.with(|last| last.replace(seen) != seen)
 }
 
 pub fn send_error(message: &str, location: &str, stack_trace: &str) {
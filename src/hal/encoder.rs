@@ -1,6 +1,8 @@
 use error::*;
 use hal::types::{Handle, EncoderHandle, NativeBool};
 use hal::analog_trigger::AnalogTriggerType;
+use hal::dio::{DigitalInput, DigitalSource, SourceBorrow};
+use std::marker::PhantomData;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[repr(u32)]
@@ -57,26 +59,76 @@ extern "C" {
     pub fn HAL_GetEncodingType(encoderHandle: EncoderHandle, status: *mut i32) -> EncodingType;
 }
 
-#[derive(Debug, Eq, PartialEq, Hash)]
-pub struct Encoder {
+/// Digital inputs allocated by an encoder that was created from channel numbers.
+#[derive(Debug)]
+struct EncoderInputs {
+    _a: DigitalInput,
+    _b: DigitalInput,
+    _index: Option<DigitalInput>,
+}
+
+/// A quadrature encoder. The digital sources it reads from are borrowed for the lifetime `'s`.
+/// Encoders created with `new` or `with_index` own their inputs, and only borrow sources passed
+/// to `set_index_source` later.
+#[derive(Debug)]
+pub struct Encoder<'s> {
     pub(crate) handle: Handle,
+    _sources: SourceBorrow<'s>,
+    // Dropped after the encoder handle is freed in `Drop`, so the HAL never has an encoder
+    // pointing at freed inputs.
+    inputs: Option<EncoderInputs>,
 }
 
-impl Encoder {
-    pub fn initialize(source_handle_a: Handle, trigger_type_a: AnalogTriggerType,
-                      source_handle_b: Handle, trigger_type_b: AnalogTriggerType,
-                      reverse_direction: bool, encoding_type: EncodingType)
-                      -> HalResult<Self> {
+unsafe fn initialize_encoder(source_a: &impl DigitalSource, source_b: &impl DigitalSource,
+                             reverse_direction: bool, encoding_type: EncodingType)
+                             -> HalResult<EncoderHandle> {
+    hal_call!(HAL_InitializeEncoder(source_a.handle(), source_a.analog_trigger_type(),
+                                    source_b.handle(), source_b.analog_trigger_type(),
+                                    reverse_direction as NativeBool, encoding_type))
+}
+
+impl<'s> Encoder<'s> {
+    /// Create an encoder on two DIO channels. The digital inputs are allocated and owned by
+    /// the encoder.
+    pub fn new(channel_a: i32, channel_b: i32, reverse_direction: bool,
+               encoding_type: EncodingType) -> HalResult<Self> {
+        let a = DigitalInput::new(channel_a)?;
+        let b = DigitalInput::new(channel_b)?;
+        let handle = unsafe { initialize_encoder(&a, &b, reverse_direction, encoding_type)? };
+
+        Ok(Encoder {
+            handle,
+            _sources: PhantomData,
+            inputs: Some(EncoderInputs { _a: a, _b: b, _index: None }),
+        })
+    }
+
+    /// Like `new`, but also uses a third DIO channel as the index pulse.
+    pub fn with_index(channel_a: i32, channel_b: i32, index_channel: i32,
+                      indexing_type: IndexingType, reverse_direction: bool,
+                      encoding_type: EncodingType) -> HalResult<Self> {
+        let index = DigitalInput::new(index_channel)?;
+        let mut encoder = Encoder::new(channel_a, channel_b, reverse_direction, encoding_type)?;
+
         unsafe {
-            hal_call!(HAL_InitializeEncoder(source_handle_a, trigger_type_a,
-                                                source_handle_b, trigger_type_b,
-                                                reverse_direction as NativeBool, encoding_type))
-                                                .map(|handle| Encoder { handle })
+            hal_call!(HAL_SetEncoderIndexSource(encoder.handle, index.handle(),
+                                                index.analog_trigger_type(), indexing_type))?;
         }
+
+        if let Some(ref mut inputs) = encoder.inputs {
+            inputs._index = Some(index);
+        }
+
+        Ok(encoder)
     }
+}
 
-    pub fn free(&self) -> HalResult<()> {
-        unsafe { hal_call!(HAL_FreeEncoder(self.handle)) }
+impl<'s> Encoder<'s> {
+    pub fn initialize(source_a: &'s impl DigitalSource, source_b: &'s impl DigitalSource,
+                      reverse_direction: bool, encoding_type: EncodingType)
+                      -> HalResult<Self> {
+        let handle = unsafe { initialize_encoder(source_a, source_b, reverse_direction, encoding_type)? };
+        Ok(Encoder { handle, _sources: PhantomData, inputs: None })
     }
 
     pub fn get(&self) -> HalResult<i32> {
@@ -127,8 +179,8 @@ impl Encoder {
         unsafe { hal_call!(HAL_SetEncoderDistancePerPulse(self.handle, distance_per_pulse)) }
     }
 
-    pub fn set_reverse_direction(&self, reverse: NativeBool) -> HalResult<()> {
-        unsafe { hal_call!(HAL_SetEncoderReverseDirection(self.handle, reverse)) }
+    pub fn set_reverse_direction(&self, reverse: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetEncoderReverseDirection(self.handle, reverse as NativeBool)) }
     }
 
     pub fn set_samples_to_average(&self, samples_to_average: i32) -> HalResult<()> {
@@ -139,8 +191,8 @@ impl Encoder {
         unsafe { hal_call!(HAL_GetEncoderSamplesToAverage(self.handle)) }
     }
 
-    pub fn set_index_source(&self, digital_source_handle: Handle, trigger_type: AnalogTriggerType, indexing_type: IndexingType) -> HalResult<()> {
-        unsafe { hal_call!(HAL_SetEncoderIndexSource(self.handle, digital_source_handle, trigger_type, indexing_type)) }
+    pub fn set_index_source(&self, source: &'s impl DigitalSource, indexing_type: IndexingType) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetEncoderIndexSource(self.handle, source.handle(), source.analog_trigger_type(), indexing_type)) }
     }
 
     pub fn get_fpga_index(&self) -> HalResult<i32> {
@@ -160,7 +212,7 @@ impl Encoder {
     }
 }
 
-impl Drop for Encoder {
+impl<'s> Drop for Encoder<'s> {
     fn drop(&mut self) {
         // AGAIN, another unused status parameter
         unsafe { HAL_FreeEncoder(self.handle, ::std::ptr::null_mut()); }
@@ -0,0 +1,366 @@
+//! Line and frame oriented readers for byte streams, mostly meant for use with `Serial`.
+
+use error::*;
+use hal::serial::{Backend, Serial};
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// What a `LineReader` does with a line longer than its maximum length.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LineOverflow {
+    /// Return the first `max_length` bytes as a line, and keep reading the rest as a new line.
+    Split,
+    /// Throw away the whole line, and return an `InvalidData` error once its end is reached.
+    Discard,
+}
+
+/// Splits a stream into lines ending in a terminator byte. The terminator is not included in
+/// the returned lines.
+#[derive(Debug)]
+pub struct LineReader<R> {
+    reader: R,
+    terminator: u8,
+    max_length: usize,
+    overflow: LineOverflow,
+    timeout: Option<Duration>,
+    // The part of the current line that has been read so far. Kept across timeouts, so that a
+    // line that comes in slowly isn't lost.
+    partial: Vec<u8>,
+    // Whether `partial` holds a whole line, which happens when a long line was split
+    complete: bool,
+    discarding: bool,
+}
+
+impl<'s> LineReader<&'s mut Serial> {
+    /// Read lines from a serial port. With `Backend::Visa` this also enables termination on
+    /// the port, so that reads return as soon as a full line arrives. The OS backend doesn't
+    /// support termination, so lines are only returned once a read fills up or times out.
+    pub fn from_serial(serial: &'s mut Serial, terminator: u8) -> HalResult<Self> {
+        if serial.backend() == Backend::Visa {
+            serial.enable_termination(terminator)?;
+        }
+        Ok(LineReader::new(serial, terminator))
+    }
+}
+
+impl<R: BufRead> LineReader<R> {
+    /// Create a line reader with a maximum line length of 1024 bytes that splits long lines and
+    /// never times out.
+    pub fn new(reader: R, terminator: u8) -> Self {
+        LineReader {
+            reader,
+            terminator,
+            max_length: 1024,
+            overflow: LineOverflow::Split,
+            timeout: None,
+            partial: Vec::new(),
+            complete: false,
+            discarding: false,
+        }
+    }
+
+    /// Set the longest line that will be returned, and what to do with longer lines. Panics if
+    /// `max_length` is 0.
+    pub fn set_max_length(&mut self, max_length: usize, overflow: LineOverflow) {
+        assert!(max_length > 0, "Maximum line length must be at least 1");
+        self.max_length = max_length;
+        self.overflow = overflow;
+    }
+
+    /// Set how long `read_line_bytes` waits for a full line before returning a `TimedOut`
+    /// error. The part of the line read so far is kept for the next call. `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn get_ref(&self) -> &R { &self.reader }
+
+    pub fn get_mut(&mut self) -> &mut R { &mut self.reader }
+
+    /// Get the underlying reader back. Any partially read line is lost.
+    pub fn into_inner(self) -> R { self.reader }
+
+    /// Read the next line as raw bytes.
+    pub fn read_line_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let start = Instant::now();
+        let terminator = self.terminator;
+
+        loop {
+            if !self.complete {
+                if let Some(timeout) = self.timeout {
+                    if start.elapsed() >= timeout {
+                        return Err(io::Error::from(io::ErrorKind::TimedOut));
+                    }
+                }
+
+                let (found_end, used) = {
+                    let available = match self.reader.fill_buf() {
+                        Ok(available) => available,
+                        // Serial reads time out regularly, the line timeout is checked above.
+                        Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+                        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(err),
+                    };
+
+                    if available.is_empty() {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                    }
+
+                    match available.iter().position(|&byte| byte == terminator) {
+                        Some(end) => {
+                            if !self.discarding { self.partial.extend_from_slice(&available[..end]); }
+                            (true, end + 1)
+                        }
+                        None => {
+                            if !self.discarding { self.partial.extend_from_slice(available); }
+                            (false, available.len())
+                        }
+                    }
+                };
+                self.reader.consume(used);
+                self.complete = found_end;
+            }
+
+            if self.partial.len() > self.max_length {
+                match self.overflow {
+                    // If the line was complete, the rest of it is returned by the next call
+                    LineOverflow::Split => {
+                        let rest = self.partial.split_off(self.max_length);
+                        return Ok(mem::replace(&mut self.partial, rest));
+                    }
+                    LineOverflow::Discard => {
+                        self.partial.clear();
+                        self.discarding = true;
+                    }
+                }
+            }
+
+            if self.complete {
+                self.complete = false;
+                if self.discarding {
+                    self.discarding = false;
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "line exceeded maximum length"));
+                }
+
+                return Ok(mem::take(&mut self.partial));
+            }
+        }
+    }
+
+    /// Read the next line as a string. Lines that aren't valid UTF-8 return an `InvalidData`
+    /// error.
+    pub fn read_line(&mut self) -> io::Result<String> {
+        String::from_utf8(self.read_line_bytes()?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Encode `data` with Consistent Overhead Byte Stuffing, so that it contains no zero bytes.
+/// The trailing zero delimiter is not included.
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_index = 0;
+    let mut code = 1u8;
+    encoded.push(0);
+
+    for (index, &byte) in data.iter().enumerate() {
+        if byte != 0 {
+            encoded.push(byte);
+            code += 1;
+        }
+
+        // A full block only needs a new one after it if there is more data, otherwise the
+        // encoding would end in a needless empty block
+        if byte == 0 || (code == 0xff && index + 1 < data.len()) {
+            encoded[code_index] = code;
+            code_index = encoded.len();
+            encoded.push(0);
+            code = 1;
+        }
+    }
+
+    encoded[code_index] = code;
+    encoded
+}
+
+/// Decode a COBS encoded frame, without its zero delimiter. Returns `None` if the frame is
+/// malformed.
+pub fn cobs_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+
+    while index < encoded.len() {
+        let code = encoded[index] as usize;
+        if code == 0 || index + code > encoded.len() {
+            return None;
+        }
+
+        let block = &encoded[index + 1..index + code];
+        if block.contains(&0) {
+            return None;
+        }
+        decoded.extend_from_slice(block);
+        index += code;
+
+        // A full block isn't followed by an implicit zero, and neither is the last block
+        if code != 0xff && index < encoded.len() {
+            decoded.push(0);
+        }
+    }
+
+    Some(decoded)
+}
+
+/// Reads zero delimited, COBS encoded frames from a stream, for binary protocols with
+/// coprocessors.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    lines: LineReader<R>,
+}
+
+impl<'s> FrameReader<&'s mut Serial> {
+    pub fn from_serial(serial: &'s mut Serial) -> HalResult<Self> {
+        LineReader::from_serial(serial, 0).map(|lines| FrameReader { lines })
+    }
+}
+
+impl<R: BufRead> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        FrameReader { lines: LineReader::new(reader, 0) }
+    }
+
+    /// Set the longest encoded frame that will be accepted. Longer frames are discarded and
+    /// reported as `InvalidData`.
+    pub fn set_max_length(&mut self, max_length: usize) {
+        self.lines.set_max_length(max_length, LineOverflow::Discard);
+    }
+
+    /// See `LineReader::set_timeout`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.lines.set_timeout(timeout);
+    }
+
+    pub fn get_ref(&self) -> &R { self.lines.get_ref() }
+
+    pub fn get_mut(&mut self) -> &mut R { self.lines.get_mut() }
+
+    pub fn into_inner(self) -> R { self.lines.into_inner() }
+
+    /// Read and decode the next frame. Empty frames, like the ones produced by back to back
+    /// delimiters, are skipped. Malformed frames return an `InvalidData` error.
+    pub fn read_frame(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            let encoded = self.lines.read_line_bytes()?;
+            if encoded.is_empty() {
+                continue;
+            }
+
+            return cobs_decode(&encoded)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed COBS frame"));
+        }
+    }
+}
+
+/// Encode `data` as a COBS frame and write it, followed by a zero delimiter.
+pub fn write_frame<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut encoded = cobs_encode(data);
+    encoded.push(0);
+    writer.write_all(&encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(data: &[u8]) {
+        let encoded = cobs_encode(data);
+        assert!(!encoded.contains(&0), "encoded {:?} contains a zero", data);
+        assert_eq!(cobs_decode(&encoded).as_ref().map(|d| &d[..]), Some(data));
+    }
+
+    #[test]
+    fn cobs_known_vectors() {
+        assert_eq!(cobs_encode(&[]), vec![0x01]);
+        assert_eq!(cobs_encode(&[0x00]), vec![0x01, 0x01]);
+        assert_eq!(cobs_encode(&[0x00, 0x00]), vec![0x01, 0x01, 0x01]);
+        assert_eq!(cobs_encode(&[0x11, 0x22, 0x00, 0x33]), vec![0x03, 0x11, 0x22, 0x02, 0x33]);
+        assert_eq!(cobs_encode(&[0x11, 0x00]), vec![0x02, 0x11, 0x01]);
+    }
+
+    #[test]
+    fn cobs_round_trips() {
+        round_trip(&[]);
+        round_trip(&[0]);
+        round_trip(&[0, 0, 0]);
+        round_trip(&[1, 2, 3, 0]);
+        round_trip(&[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn cobs_long_runs() {
+        // 254 non-zero bytes fill a block exactly, 255 spill into a second one
+        for &len in &[253, 254, 255, 508, 509, 1000] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 255) as u8 + 1).collect();
+            round_trip(&data);
+
+            let mut trailing = data.clone();
+            trailing.push(0);
+            round_trip(&trailing);
+        }
+
+        let encoded = cobs_encode(&[1; 254]);
+        assert_eq!(encoded.len(), 255);
+        assert_eq!(encoded[0], 0xff);
+    }
+
+    #[test]
+    fn cobs_rejects_malformed() {
+        assert_eq!(cobs_decode(&[0x00]), None);
+        assert_eq!(cobs_decode(&[0x05, 0x11]), None);
+        assert_eq!(cobs_decode(&[0x03, 0x11, 0x00]), None);
+    }
+
+    #[test]
+    fn lines_are_split_on_the_terminator() {
+        let mut lines = LineReader::new(Cursor::new(&b"one\ntwo\n\nthree"[..]), b'\n');
+        assert_eq!(lines.read_line().unwrap(), "one");
+        assert_eq!(lines.read_line().unwrap(), "two");
+        assert_eq!(lines.read_line().unwrap(), "");
+        assert_eq!(lines.read_line().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn long_lines_are_split() {
+        let mut lines = LineReader::new(Cursor::new(&b"abcdefgh\nxy\n"[..]), b'\n');
+        lines.set_max_length(3, LineOverflow::Split);
+        assert_eq!(lines.read_line().unwrap(), "abc");
+        assert_eq!(lines.read_line().unwrap(), "def");
+        assert_eq!(lines.read_line().unwrap(), "gh");
+        assert_eq!(lines.read_line().unwrap(), "xy");
+    }
+
+    #[test]
+    fn long_lines_are_discarded() {
+        let mut lines = LineReader::new(Cursor::new(&b"abcdefgh\nxy\n"[..]), b'\n');
+        lines.set_max_length(3, LineOverflow::Discard);
+        assert_eq!(lines.read_line().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(lines.read_line().unwrap(), "xy");
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &[1, 0, 2]).unwrap();
+        stream.push(0);
+        write_frame(&mut stream, &[]).unwrap();
+        write_frame(&mut stream, &[0; 300]).unwrap();
+
+        let mut frames = FrameReader::new(Cursor::new(stream));
+        assert_eq!(frames.read_frame().unwrap(), vec![1, 0, 2]);
+        // The empty frame encodes to a single code byte, so it isn't skipped
+        assert_eq!(frames.read_frame().unwrap(), vec![]);
+        assert_eq!(frames.read_frame().unwrap(), vec![0; 300]);
+    }
+}
@@ -0,0 +1,306 @@
+//! Named access to gamepad controls, with mappings for the layouts the driver station reports
+//! for common controllers.
+
+use error::*;
+use hal::driverstation;
+use hal::joystick::{Joystick, RumbleType};
+use std::ops::Deref;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+/// The analog controls of a gamepad. Sticks range from -1 to 1, with up being negative like on
+/// the driver station. Triggers range from 0 to 1.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// The buttons of a gamepad, named after their Xbox equivalents.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum GamepadButton {
+    A,
+    B,
+    X,
+    Y,
+    LeftBumper,
+    RightBumper,
+    Back,
+    Start,
+    LeftStick,
+    RightStick,
+}
+
+/// Where the value of an analog control comes from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AxisSource {
+    /// A joystick axis, mapped with `raw * scale + offset`.
+    Axis { index: usize, scale: f32, offset: f32 },
+    /// A digital button, read as 0 or 1. Some controllers only report their triggers this way.
+    Button(usize),
+    /// The controller doesn't have this control, so it always reads 0.
+    None,
+}
+
+impl AxisSource {
+    /// An axis that is used as is.
+    pub const fn axis(index: usize) -> Self {
+        AxisSource::Axis { index, scale: 1.0, offset: 0.0 }
+    }
+}
+
+/// Describes where each named control is on a particular controller. Buttons are numbered
+/// starting at 1, like on the driver station.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GamepadMapping {
+    pub left_x: AxisSource,
+    pub left_y: AxisSource,
+    pub right_x: AxisSource,
+    pub right_y: AxisSource,
+    pub left_trigger: AxisSource,
+    pub right_trigger: AxisSource,
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+    pub x: Option<usize>,
+    pub y: Option<usize>,
+    pub left_bumper: Option<usize>,
+    pub right_bumper: Option<usize>,
+    pub back: Option<usize>,
+    pub start: Option<usize>,
+    pub left_stick: Option<usize>,
+    pub right_stick: Option<usize>,
+    /// The POV used as the D-pad.
+    pub dpad: Option<usize>,
+}
+
+impl GamepadMapping {
+    /// Xbox 360 and Xbox One controllers, and anything else in XInput mode, like a Logitech
+    /// F310 with its switch set to X.
+    pub const XBOX: GamepadMapping = GamepadMapping {
+        left_x: AxisSource::axis(0),
+        left_y: AxisSource::axis(1),
+        right_x: AxisSource::axis(4),
+        right_y: AxisSource::axis(5),
+        left_trigger: AxisSource::axis(2),
+        right_trigger: AxisSource::axis(3),
+        a: Some(1),
+        b: Some(2),
+        x: Some(3),
+        y: Some(4),
+        left_bumper: Some(5),
+        right_bumper: Some(6),
+        back: Some(7),
+        start: Some(8),
+        left_stick: Some(9),
+        right_stick: Some(10),
+        dpad: Some(0),
+    };
+
+    /// Sony DualShock 4. Cross, circle, square and triangle are A, B, X and Y, and share and
+    /// options are back and start. The triggers range from -1 to 1 on the driver station.
+    pub const PS4: GamepadMapping = GamepadMapping {
+        left_x: AxisSource::axis(0),
+        left_y: AxisSource::axis(1),
+        right_x: AxisSource::axis(2),
+        right_y: AxisSource::axis(5),
+        left_trigger: AxisSource::Axis { index: 3, scale: 0.5, offset: 0.5 },
+        right_trigger: AxisSource::Axis { index: 4, scale: 0.5, offset: 0.5 },
+        a: Some(2),
+        b: Some(3),
+        x: Some(1),
+        y: Some(4),
+        left_bumper: Some(5),
+        right_bumper: Some(6),
+        back: Some(9),
+        start: Some(10),
+        left_stick: Some(11),
+        right_stick: Some(12),
+        dpad: Some(0),
+    };
+
+    /// Logitech F310 and similar gamepads in DirectInput mode, with the switch set to D. The
+    /// triggers are buttons in this mode.
+    pub const LOGITECH_DIRECTINPUT: GamepadMapping = GamepadMapping {
+        left_x: AxisSource::axis(0),
+        left_y: AxisSource::axis(1),
+        right_x: AxisSource::axis(2),
+        right_y: AxisSource::axis(3),
+        left_trigger: AxisSource::Button(7),
+        right_trigger: AxisSource::Button(8),
+        a: Some(2),
+        b: Some(3),
+        x: Some(1),
+        y: Some(4),
+        left_bumper: Some(5),
+        right_bumper: Some(6),
+        back: Some(9),
+        start: Some(10),
+        left_stick: Some(11),
+        right_stick: Some(12),
+        dpad: Some(0),
+    };
+
+    pub fn axis(&self, axis: GamepadAxis) -> AxisSource {
+        match axis {
+            GamepadAxis::LeftX => self.left_x,
+            GamepadAxis::LeftY => self.left_y,
+            GamepadAxis::RightX => self.right_x,
+            GamepadAxis::RightY => self.right_y,
+            GamepadAxis::LeftTrigger => self.left_trigger,
+            GamepadAxis::RightTrigger => self.right_trigger,
+        }
+    }
+
+    pub fn button(&self, button: GamepadButton) -> Option<usize> {
+        match button {
+            GamepadButton::A => self.a,
+            GamepadButton::B => self.b,
+            GamepadButton::X => self.x,
+            GamepadButton::Y => self.y,
+            GamepadButton::LeftBumper => self.left_bumper,
+            GamepadButton::RightBumper => self.right_bumper,
+            GamepadButton::Back => self.back,
+            GamepadButton::Start => self.start,
+            GamepadButton::LeftStick => self.left_stick,
+            GamepadButton::RightStick => self.right_stick,
+        }
+    }
+}
+
+/// A gamepad on a driver station port, read through a `GamepadMapping`.
+#[derive(Debug)]
+pub struct Gamepad {
+    joystick: Joystick,
+    mapping: GamepadMapping,
+}
+
+impl Gamepad {
+    pub fn new(port: i32, mapping: GamepadMapping) -> HalResult<Self> {
+        Ok(Gamepad { joystick: Joystick::new(port)?, mapping })
+    }
+
+    pub fn joystick(&self) -> &Joystick { &self.joystick }
+
+    pub fn mapping(&self) -> &GamepadMapping { &self.mapping }
+
+    /// Get the value of an analog control. Controls that the controller doesn't have, or that
+    /// aren't plugged in, read 0.
+    pub fn axis(&self, axis: GamepadAxis) -> HalResult<f32> {
+        Ok(match self.mapping.axis(axis) {
+            AxisSource::Axis { index, scale, offset } => {
+                self.joystick.axis(index)?.map(|raw| raw * scale + offset).unwrap_or(0.0)
+            }
+            AxisSource::Button(button) => if self.joystick.button(button)? { 1.0 } else { 0.0 },
+            AxisSource::None => 0.0,
+        })
+    }
+
+    /// Get whether a button is pressed. Buttons that the controller doesn't have are never
+    /// pressed.
+    pub fn button(&self, button: GamepadButton) -> HalResult<bool> {
+        match self.mapping.button(button) {
+            Some(button) => self.joystick.button(button),
+            None => Ok(false),
+        }
+    }
+
+    /// Get the `(x, y)` position of a stick.
+    pub fn stick(&self, hand: Hand) -> HalResult<(f32, f32)> {
+        match hand {
+            Hand::Left => Ok((self.axis(GamepadAxis::LeftX)?, self.axis(GamepadAxis::LeftY)?)),
+            Hand::Right => Ok((self.axis(GamepadAxis::RightX)?, self.axis(GamepadAxis::RightY)?)),
+        }
+    }
+
+    pub fn trigger(&self, hand: Hand) -> HalResult<f32> {
+        self.axis(match hand {
+            Hand::Left => GamepadAxis::LeftTrigger,
+            Hand::Right => GamepadAxis::RightTrigger,
+        })
+    }
+
+    pub fn bumper(&self, hand: Hand) -> HalResult<bool> {
+        self.button(match hand {
+            Hand::Left => GamepadButton::LeftBumper,
+            Hand::Right => GamepadButton::RightBumper,
+        })
+    }
+
+    /// Get whether a stick is pushed in.
+    pub fn stick_button(&self, hand: Hand) -> HalResult<bool> {
+        self.button(match hand {
+            Hand::Left => GamepadButton::LeftStick,
+            Hand::Right => GamepadButton::RightStick,
+        })
+    }
+
+    pub fn a(&self) -> HalResult<bool> { self.button(GamepadButton::A) }
+    pub fn b(&self) -> HalResult<bool> { self.button(GamepadButton::B) }
+    pub fn x(&self) -> HalResult<bool> { self.button(GamepadButton::X) }
+    pub fn y(&self) -> HalResult<bool> { self.button(GamepadButton::Y) }
+    pub fn back(&self) -> HalResult<bool> { self.button(GamepadButton::Back) }
+    pub fn start(&self) -> HalResult<bool> { self.button(GamepadButton::Start) }
+
+    /// Get the D-pad angle in degrees, clockwise from up. Returns `None` if it isn't pressed.
+    pub fn dpad(&self) -> HalResult<Option<i16>> {
+        let pov = match self.mapping.dpad {
+            Some(pov) => self.joystick.pov(pov)?,
+            None => None,
+        };
+
+        Ok(pov.and_then(|angle| if angle < 0 { None } else { Some(angle) }))
+    }
+
+    /// Rumble one side of the controller. `intensity` is clamped between 0 and 1.
+    pub fn set_rumble(&self, hand: Hand, intensity: f64) -> HalResult<()> {
+        self.joystick.set_rumble(match hand {
+            Hand::Left => RumbleType::Left,
+            Hand::Right => RumbleType::Right,
+        }, intensity)
+    }
+}
+
+/// An Xbox controller. All of the named controls are available through `Gamepad`.
+#[derive(Debug)]
+pub struct XboxController {
+    gamepad: Gamepad,
+}
+
+impl XboxController {
+    /// Use the Xbox controller on driver station port `port`. If a controller that isn't an
+    /// Xbox controller is plugged into the port, a warning is sent to the driver station.
+    pub fn new(port: i32) -> HalResult<Self> {
+        let controller = XboxController { gamepad: Gamepad::new(port, GamepadMapping::XBOX)? };
+        controller.check_controller();
+        Ok(controller)
+    }
+
+    /// Check that the controller plugged in is an Xbox controller, sending a warning to the
+    /// driver station if it isn't. Returns `false` only if a different controller is plugged
+    /// in, since the port reads as empty until the driver station connects.
+    pub fn check_controller(&self) -> bool {
+        let joystick = self.gamepad.joystick();
+        let name = joystick.get_name();
+        if name.is_empty() || joystick.is_xbox() {
+            return true;
+        }
+
+        driverstation::send_warning(&format!(
+            "Joystick {} is used as an Xbox controller, but \"{}\" is plugged in",
+            joystick.port(), name));
+        false
+    }
+}
+
+impl Deref for XboxController {
+    type Target = Gamepad;
+    fn deref(&self) -> &Gamepad { &self.gamepad }
+}
@@ -15,3 +15,112 @@ pub enum I2cPort {
     OnBoard = 0,
     MXP = 1,
 }
+
+/// The HAL returns -1 from I2C transfers when they are aborted
+fn check_transfer(result: i32) -> HalResult<()> {
+    if result < 0 { Err(HalError::TransferAborted) } else { Ok(()) }
+}
+
+/// A device on an I2C bus, addressed with a 7-bit address.
+#[derive(Debug, Eq, PartialEq, Hash)]
+pub struct I2c {
+    port: I2cPort,
+    address: i32,
+}
+
+impl I2c {
+    /// Open the I2C bus on `port` for the device at `address`. Returns
+    /// `HalError::InvalidAddress` if the address does not fit in 7 bits.
+    pub fn new(port: I2cPort, address: u8) -> HalResult<Self> {
+        if address > 0x7f { return Err(HalError::InvalidAddress(address as i32)); }
+        // The HAL reference counts initializations of each port, and closes it once every
+        // user has called `HAL_CloseI2C`
+        unsafe { hal_call!(HAL_InitializeI2C(port))?; }
+
+        Ok(I2c { port, address: address as i32 })
+    }
+
+    pub fn port(&self) -> I2cPort { self.port }
+
+    pub fn address(&self) -> u8 { self.address as u8 }
+
+    /// Write `send` to the device, and then read `receive.len()` bytes back in a single
+    /// transaction. Either buffer may be empty.
+    pub fn transaction(&self, send: &[u8], receive: &mut [u8]) -> HalResult<()> {
+        check_transfer(unsafe {
+            HAL_TransactionI2C(self.port, self.address, send.as_ptr(), send.len() as i32,
+                               receive.as_mut_ptr(), receive.len() as i32)
+        })
+    }
+
+    /// Do a transaction with no data, to check if a device acknowledges the address.
+    pub fn address_only(&self) -> HalResult<()> {
+        self.transaction(&[], &mut [])
+    }
+
+    /// Write a single byte to a register.
+    pub fn write(&self, register: u8, data: u8) -> HalResult<()> {
+        self.write_bulk(&[register, data])
+    }
+
+    /// Write `data` to the device, without reading anything back.
+    pub fn write_bulk(&self, data: &[u8]) -> HalResult<()> {
+        check_transfer(unsafe { HAL_WriteI2C(self.port, self.address, data.as_ptr(), data.len() as i32) })
+    }
+
+    /// Read `buffer.len()` bytes starting from `register`.
+    pub fn read(&self, register: u8, buffer: &mut [u8]) -> HalResult<()> {
+        self.transaction(&[register], buffer)
+    }
+
+    /// Read `buffer.len()` bytes from the device without first sending a register address.
+    pub fn read_only(&self, buffer: &mut [u8]) -> HalResult<()> {
+        check_transfer(unsafe { HAL_ReadI2C(self.port, self.address, buffer.as_mut_ptr(), buffer.len() as i32) })
+    }
+
+    pub fn read_register(&self, register: u8) -> HalResult<u8> {
+        let mut value = [0];
+        self.read(register, &mut value)?;
+        Ok(value[0])
+    }
+
+    pub fn write_register(&self, register: u8, value: u8) -> HalResult<()> {
+        self.write(register, value)
+    }
+
+    /// Read a register, pass its value through `func`, and write the result back. Returns the
+    /// value that was written.
+    pub fn update_register<F: FnOnce(u8) -> u8>(&self, register: u8, func: F) -> HalResult<u8> {
+        let value = func(self.read_register(register)?);
+        self.write_register(register, value)?;
+        Ok(value)
+    }
+
+    /// Set the bits of a register selected by `mask` to the matching bits of `value`, leaving
+    /// the other bits untouched.
+    pub fn update_register_bits(&self, register: u8, mask: u8, value: u8) -> HalResult<u8> {
+        self.update_register(register, |old| (old & !mask) | (value & mask))
+    }
+
+    /// Probe every non-reserved 7-bit address on `port`, returning the ones that acknowledged.
+    pub fn scan(port: I2cPort) -> HalResult<Vec<u8>> {
+        let mut bus = I2c::new(port, 0)?;
+        let mut found = vec![];
+
+        // 0x00-0x07 and 0x78-0x7f are reserved by the I2C spec
+        for address in 0x08..0x78 {
+            bus.address = address;
+            if bus.address_only().is_ok() {
+                found.push(address as u8);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+impl Drop for I2c {
+    fn drop(&mut self) {
+        unsafe { HAL_CloseI2C(self.port); }
+    }
+}
@@ -1,5 +1,8 @@
 use std::os::raw::{c_void, c_double};
 use hal::analog_trigger::AnalogTriggerType;
+use hal::dio::{DigitalSource, SourceBorrow};
+use std::marker::PhantomData;
+use hal::time::FpgaInstant;
 use hal::types::{InterruptHandle, Handle, NativeBool};
 use error::*;
 
@@ -40,41 +43,76 @@ pub enum SyncWaitResult {
 }
 
 /// Synchronous interrupt handler. Users of the API will need to explicitly call `wait` and wait
-/// for an interrupt to happen.
+/// for an interrupt to happen. The source that triggers interrupts is borrowed for the lifetime
+/// `'s`.
 #[derive(Debug)]
-pub struct InterruptHandlerSync {
-    pub(crate) handle: Handle
+pub struct InterruptHandlerSync<'s> {
+    pub(crate) handle: Handle,
+    _source: SourceBorrow<'s>,
 }
 
-impl InterruptHandlerSync {
+impl<'s> InterruptHandlerSync<'s> {
     pub fn new() -> HalResult<Self> {
         unsafe {
             // sync version, set watcher to false
-            hal_call!(HAL_InitializeInterrupts(0)).map(|handle| InterruptHandlerSync { handle })
+            hal_call!(HAL_InitializeInterrupts(0))
+                .map(|handle| InterruptHandlerSync { handle, _source: PhantomData })
         }
     }
 
+    /// Set the source that triggers interrupts.
+    pub fn request_interrupts(&self, source: &'s impl DigitalSource) -> HalResult<()> {
+        unsafe { hal_call!(HAL_RequestInterrupts(self.handle, source.handle(), source.analog_trigger_type())) }
+    }
+
+    /// Set which edges of the source trigger an interrupt.
+    pub fn set_edge(&self, rising: bool, falling: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetInterruptUpSourceEdge(self.handle, rising as NativeBool, falling as NativeBool)) }
+    }
+
     /// Wait at most `timeout` seconds for an interrupt to occur.
     pub fn wait(&self, timeout: f64, ignore_previous: bool) -> HalResult<i64> {
         unsafe { hal_call!(HAL_WaitForInterrupt(self.handle, timeout as c_double, ignore_previous as NativeBool)) }
     }
+
+    /// Get the time of the last rising edge.
+    pub fn read_rising_timestamp(&self) -> HalResult<FpgaInstant> {
+        unsafe { hal_call!(HAL_ReadInterruptRisingTimestamp(self.handle)).and_then(FpgaInstant::from_interrupt_timestamp) }
+    }
+
+    /// Get the time of the last falling edge.
+    pub fn read_falling_timestamp(&self) -> HalResult<FpgaInstant> {
+        unsafe { hal_call!(HAL_ReadInterruptFallingTimestamp(self.handle)).and_then(FpgaInstant::from_interrupt_timestamp) }
+    }
 }
 
 /// Asynchronous interrupt handler. Users of the API provide a function to be called every time
-/// an interrupt is fired.
+/// an interrupt is fired. The source that triggers interrupts is borrowed for the lifetime `'s`.
 #[derive(Debug)]
-pub struct InterruptHandler {
-    pub(crate) handle: Handle
+pub struct InterruptHandler<'s> {
+    pub(crate) handle: Handle,
+    _source: SourceBorrow<'s>,
 }
 
-impl InterruptHandler {
+impl<'s> InterruptHandler<'s> {
     pub fn new() -> HalResult<Self> {
         unsafe {
             // async version, set watcher to true
-            hal_call!(HAL_InitializeInterrupts(1)).map(|handle| InterruptHandler { handle })
+            hal_call!(HAL_InitializeInterrupts(1))
+                .map(|handle| InterruptHandler { handle, _source: PhantomData })
         }
     }
 
+    /// Set the source that triggers interrupts.
+    pub fn request_interrupts(&self, source: &'s impl DigitalSource) -> HalResult<()> {
+        unsafe { hal_call!(HAL_RequestInterrupts(self.handle, source.handle(), source.analog_trigger_type())) }
+    }
+
+    /// Set which edges of the source trigger an interrupt.
+    pub fn set_edge(&self, rising: bool, falling: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetInterruptUpSourceEdge(self.handle, rising as NativeBool, falling as NativeBool)) }
+    }
+
     pub fn enable(&self) -> HalResult<()> {
         unsafe { hal_call!(HAL_EnableInterrupts(self.handle)) }
     }
@@ -108,7 +146,7 @@ impl InterruptHandler {
     }
 }
 
-impl Drop for InterruptHandler {
+impl<'s> Drop for InterruptHandler<'s> {
     fn drop(&mut self) {
         // AGAIN, this function has a status param that isn't used
         unsafe { HAL_CleanInterrupts(self.handle, ::std::ptr::null_mut()) }
@@ -1,6 +1,10 @@
+use std::collections::VecDeque;
+use std::ffi::CStr;
 use std::os::raw::*;
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use hal::driverstation;
 use hal::types::*;
-use error::HalResult;
+use error::*;
 
 extern "C" {
     fn HAL_GetJoystickAxes(joystick: i32, axes: *mut Axes) -> i32;
@@ -10,12 +14,16 @@ extern "C" {
     fn HAL_GetJoystickIsXbox(joystick: i32) -> NativeBool;
     fn HAL_GetJoystickType(joystick: i32) -> i32;
     fn HAL_GetJoystickName(joystick: i32) -> *mut c_char;
-    fn HAL_FreeJoystickName(name: *mut c_char);
     fn HAL_GetJoystickAxisType(joystick: i32, axis: i32) -> i32;
     fn HAL_SetJoystickOutputs(joystick: i32, outputs: i64, left_rumble: i32, right_rumble: i32) -> i32;
 
+    // This version of the HAL has no `HAL_FreeJoystickName`, the name is allocated with `malloc`
+    fn free(ptr: *mut c_void);
 }
 
+/// The number of joystick ports on the driver station.
+pub const MAX_JOYSTICKS: i32 = 6;
+
 const MAX_JOYSTICK_AXES: usize = 12;
 const MAX_JOYSTICK_POVS: usize = 12;
 
@@ -26,6 +34,18 @@ pub struct Povs {
     povs: [i16; MAX_JOYSTICK_POVS]
 }
 
+impl Povs {
+    /// Get the angle of the specified POV in degrees, or -1 if it isn't pressed. Returns `None`
+    /// if the joystick doesn't have that POV.
+    pub fn get(&self, idx: usize) -> Option<i16> {
+        if idx >= self.count() { None } else {
+            self.povs.get(idx).cloned()
+        }
+    }
+
+    pub fn count(&self) -> usize { self.count.max(0) as usize }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Default)]
 pub struct Buttons {
@@ -33,15 +53,82 @@ pub struct Buttons {
     count: u8
 }
 
+impl Buttons {
+    /// Get whether a button is pressed. Buttons are numbered starting at 1, like they are on
+    /// the driver station. Buttons the joystick doesn't have are never pressed.
+    pub fn get(&self, button: usize) -> bool {
+        button != 0 && button <= self.count() && self.buttons >> (button - 1) & 1 != 0
+    }
+
+    pub fn count(&self) -> usize { self.count as usize }
+
+    /// Get the state of every button as a bitmask, with button 1 in the lowest bit.
+    pub fn raw(&self) -> u32 { self.buttons }
+}
+
+/// The kind of controller plugged into a joystick port, as reported by the driver station.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum JoystickType {
+    Unknown,
+    XInputUnknown,
+    XInputGamepad,
+    XInputWheel,
+    XInputArcadeStick,
+    XInputFlightStick,
+    XInputDancePad,
+    XInputGuitar,
+    XInputGuitar2,
+    XInputDrumKit,
+    XInputGuitar3,
+    XInputArcadePad,
+    HidJoystick,
+    HidGamepad,
+    HidDriving,
+    HidFlight,
+    HidFirstPerson,
+}
+
+impl JoystickType {
+    pub fn from_raw(raw: i32) -> Self {
+        use self::JoystickType::*;
+        match raw {
+            0 => XInputUnknown,
+            1 => XInputGamepad,
+            2 => XInputWheel,
+            3 => XInputArcadeStick,
+            4 => XInputFlightStick,
+            5 => XInputDancePad,
+            6 => XInputGuitar,
+            7 => XInputGuitar2,
+            8 => XInputDrumKit,
+            11 => XInputGuitar3,
+            19 => XInputArcadePad,
+            20 => HidJoystick,
+            21 => HidGamepad,
+            22 => HidDriving,
+            23 => HidFlight,
+            24 => HidFirstPerson,
+            _ => Unknown,
+        }
+    }
+}
+
+/// Which side of a controller to rumble.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum RumbleType {
+    Left,
+    Right,
+}
+
 #[repr(C)]
 pub struct Descriptor {
     is_xbox: u8,
     stick_type: u8,
     name: [c_char; 256],
-    _axis_count: u8,
+    axis_count: u8,
     axis_types: [u8; MAX_JOYSTICK_AXES],
-    _pov_count: u8,
-    _button_count: u8,
+    button_count: u8,
+    pov_count: u8,
 }
 
 impl ::std::fmt::Debug for Descriptor {
@@ -50,10 +137,10 @@ impl ::std::fmt::Debug for Descriptor {
             .field("is_xbox", &self.is_xbox)
             .field("stick_type", &self.stick_type)
             .field("name", &&self.name[..]) // bugger
-            .field("_axis_count", &self._axis_count)
+            .field("axis_count", &self.axis_count)
             .field("axis_types", &self.axis_types)
-            .field("_pov_count", &self._pov_count)
-            .field("_button_count", &self._button_count)
+            .field("button_count", &self.button_count)
+            .field("pov_count", &self.pov_count)
             .finish()
     }
 }
@@ -67,9 +154,24 @@ impl Descriptor {
 
     pub fn is_xbox(&self) -> bool { self.is_xbox != 0 }
     
-    /// Get the "type" of the joystick, whatever that means
+    /// Get the raw type of the joystick. See `joystick_type` for the typed version.
     pub fn get_type(&self) -> u8 { self.stick_type }
 
+    pub fn joystick_type(&self) -> JoystickType {
+        // The type is stored as a `u8`, so -1 (unknown) comes back as 255
+        JoystickType::from_raw(self.stick_type as i8 as i32)
+    }
+
+    pub fn axis_count(&self) -> usize { self.axis_count as usize }
+    pub fn button_count(&self) -> usize { self.button_count as usize }
+    pub fn pov_count(&self) -> usize { self.pov_count as usize }
+
+    /// Get the raw HID type of the specified axis, or `None` if the joystick doesn't have it.
+    pub fn axis_type(&self, axis: usize) -> Option<u8> {
+        if axis >= self.axis_count() || axis >= MAX_JOYSTICK_AXES { None } else {
+            Some(self.axis_types[axis])
+        }
+    }
 }
 
 #[repr(C)]
@@ -82,23 +184,117 @@ pub struct Axes {
 impl Axes {
     /// Get the value for the specified axis.
     pub fn get(&self, idx: usize) -> Option<f32> {
-        if idx as i16 >= self.count { None } else {
-            Some(self.axes[idx] as f32)
+        if idx >= self.count() { None } else {
+            self.axes.get(idx).cloned()
         }
     }
-}
 
-#[derive(Debug)]
-struct XboxController {
-    numer: i32,
+    pub fn count(&self) -> usize { self.count.max(0) as usize }
 }
 
 #[derive(Debug)]
 pub struct Joystick {
     pub(crate) number: i32,
+    // The HAL sets outputs and both rumbles at once, so the last values have to be kept around.
+    // Atomic so that a joystick can still be shared between threads
+    outputs: AtomicU32,
+    left_rumble: AtomicU16,
+    right_rumble: AtomicU16,
 }
 
 impl Joystick {
+    /// Use the joystick on driver station port `port`, from 0 to 5.
+    pub fn new(port: i32) -> HalResult<Joystick> {
+        if !(0..MAX_JOYSTICKS).contains(&port) { return Err(HalError::InvalidChannel(port)); }
+        Ok(Joystick {
+            number: port,
+            outputs: AtomicU32::new(0),
+            left_rumble: AtomicU16::new(0),
+            right_rumble: AtomicU16::new(0),
+        })
+    }
+
+    pub fn port(&self) -> i32 { self.number }
+
+    /// Get the value of a single axis. Returns `None` if the joystick doesn't have that axis.
+    pub fn axis(&self, axis: usize) -> HalResult<Option<f32>> {
+        self.get_axes().map(|axes| axes.get(axis))
+    }
+
+    /// Get whether a button is pressed. Buttons are numbered starting at 1.
+    pub fn button(&self, button: usize) -> HalResult<bool> {
+        self.get_buttons().map(|buttons| buttons.get(button))
+    }
+
+    /// Get the angle of a POV in degrees, or -1 if it isn't pressed. Returns `None` if the
+    /// joystick doesn't have that POV.
+    pub fn pov(&self, pov: usize) -> HalResult<Option<i16>> {
+        self.get_povs().map(|povs| povs.get(pov))
+    }
+
+    pub fn is_xbox(&self) -> bool {
+        unsafe { HAL_GetJoystickIsXbox(self.number) != 0 }
+    }
+
+    pub fn get_type(&self) -> JoystickType {
+        JoystickType::from_raw(unsafe { HAL_GetJoystickType(self.number) })
+    }
+
+    /// Get the raw HID type of the specified axis, or `None` if the joystick doesn't have it.
+    pub fn get_axis_type(&self, axis: i32) -> Option<i32> {
+        match unsafe { HAL_GetJoystickAxisType(self.number, axis) } {
+            -1 => None,
+            ty => Some(ty),
+        }
+    }
+
+    /// Get the name of the joystick, which is empty if nothing is plugged in.
+    pub fn get_name(&self) -> String {
+        unsafe {
+            let raw = HAL_GetJoystickName(self.number);
+            if raw.is_null() { return String::new(); }
+            let name = CStr::from_ptr(raw).to_string_lossy().into_owned();
+            free(raw as *mut c_void);
+            name
+        }
+    }
+
+    fn update_outputs(&self) -> HalResult<()> {
+        unsafe {
+            hal_call!(ret HAL_SetJoystickOutputs(self.number, self.outputs.load(Ordering::SeqCst) as i64,
+                                                 self.left_rumble.load(Ordering::SeqCst) as i32,
+                                                 self.right_rumble.load(Ordering::SeqCst) as i32))
+        }
+    }
+
+    /// Set one side of the controller rumbling. `intensity` is clamped between 0 and 1.
+    pub fn set_rumble(&self, side: RumbleType, intensity: f64) -> HalResult<()> {
+        let value = (intensity.clamp(0.0, 1.0) * 65535.0) as u16;
+        match side {
+            RumbleType::Left => self.left_rumble.store(value, Ordering::SeqCst),
+            RumbleType::Right => self.right_rumble.store(value, Ordering::SeqCst),
+        }
+        self.update_outputs()
+    }
+
+    /// Set a single HID output. Outputs are numbered starting at 1, up to 32.
+    pub fn set_output(&self, output: u32, value: bool) -> HalResult<()> {
+        require_value_between!(output, 1, 32);
+        let mask = 1 << (output - 1);
+        if value {
+            self.outputs.fetch_or(mask, Ordering::SeqCst);
+        } else {
+            self.outputs.fetch_and(!mask, Ordering::SeqCst);
+        }
+        self.update_outputs()
+    }
+
+    /// Set every HID output at once, with output 1 in the lowest bit.
+    pub fn set_outputs(&self, outputs: u32) -> HalResult<()> {
+        self.outputs.store(outputs, Ordering::SeqCst);
+        self.update_outputs()
+    }
+
     pub fn get_axes(&self) -> HalResult<Axes> {
         let mut axes = Axes::default();
         unsafe { hal_call!(ret HAL_GetJoystickAxes(self.number, &mut axes))? };
@@ -119,9 +315,160 @@ impl Joystick {
 
     pub fn get_descriptor(&self) -> HalResult<Descriptor> {
         unsafe {
-            let mut descriptor: Descriptor = ::std::mem::uninitialized();
+            let mut descriptor: Descriptor = ::std::mem::zeroed();
             hal_call!(ret HAL_GetJoystickDescriptor(self.number, &mut descriptor))?;
             Ok(descriptor)
         }
     }
 }
+
+// Changes queued for a single POV are capped, so a POV that is never checked doesn't grow
+// without bound. The oldest changes are dropped first.
+const MAX_QUEUED_POV_CHANGES: usize = 32;
+
+/// Tracks button and POV changes of a joystick from one driver station packet to the next, so
+/// that each press, release and POV change is reported exactly once.
+///
+/// Changes are only latched when a new packet has arrived, so calling `update` more often than
+/// packets arrive doesn't double count anything, and presses are counted rather than flagged,
+/// so several presses between checks are all reported. A button pressed and released within a
+/// single packet can't be seen by the driver station, and so can't be seen here either.
+///
+/// Each tracker remembers the last packet it latched, so several of them can be updated from
+/// the same thread.
+#[derive(Debug)]
+pub struct JoystickState {
+    joystick: Joystick,
+    // `None` until the first packet, so buttons held at startup don't count as presses
+    previous: Option<(Buttons, Povs)>,
+    presses: [u32; 32],
+    releases: [u32; 32],
+    pov_changes: Vec<VecDeque<i16>>,
+    last_packet: u64,
+}
+
+impl JoystickState {
+    pub fn new(port: i32) -> HalResult<Self> {
+        Ok(JoystickState {
+            joystick: Joystick::new(port)?,
+            previous: None,
+            presses: [0; 32],
+            releases: [0; 32],
+            pov_changes: vec![VecDeque::new(); MAX_JOYSTICK_POVS],
+            last_packet: 0,
+        })
+    }
+
+    pub fn joystick(&self) -> &Joystick { &self.joystick }
+
+    /// Latch the latest packet if one arrived since this tracker last latched one. Returns
+    /// whether a new packet was latched.
+    pub fn update(&mut self) -> HalResult<bool> {
+        let packet = driverstation::packets_seen();
+        if packet == self.last_packet {
+            return Ok(false);
+        }
+
+        self.last_packet = packet;
+        self.latch()?;
+        Ok(true)
+    }
+
+    /// Wait for the next packet, then latch it. `timeout` works like it does for
+    /// `driverstation::wait_for_data`. Returns whether a new packet was latched.
+    pub fn wait_and_update(&mut self, timeout: Option<f64>) -> HalResult<bool> {
+        if !driverstation::wait_for_data(timeout) {
+            return Ok(false);
+        }
+
+        // Count the packet we woke up for, so the next `update` doesn't latch it again
+        self.last_packet = driverstation::packets_seen();
+        self.latch()?;
+        Ok(true)
+    }
+
+    /// Record the changes between the last latched packet and the current one. This should be
+    /// called exactly once per packet; `update` and `wait_and_update` take care of that.
+    pub fn latch(&mut self) -> HalResult<()> {
+        let buttons = self.joystick.get_buttons()?;
+        let povs = self.joystick.get_povs()?;
+
+        if let Some((old_buttons, old_povs)) = self.previous {
+            let pressed = buttons.raw() & !old_buttons.raw();
+            let released = !buttons.raw() & old_buttons.raw();
+            for bit in 0..32 {
+                if pressed >> bit & 1 != 0 { self.presses[bit] = self.presses[bit].saturating_add(1); }
+                if released >> bit & 1 != 0 { self.releases[bit] = self.releases[bit].saturating_add(1); }
+            }
+
+            for (idx, changes) in self.pov_changes.iter_mut().enumerate() {
+                let angle = povs.get(idx).unwrap_or(-1);
+                if old_povs.get(idx).unwrap_or(-1) != angle {
+                    if changes.len() == MAX_QUEUED_POV_CHANGES { changes.pop_front(); }
+                    changes.push_back(angle);
+                }
+            }
+        }
+
+        self.previous = Some((buttons, povs));
+        Ok(())
+    }
+
+    /// Get the buttons as of the last latched packet.
+    pub fn buttons(&self) -> Buttons {
+        self.previous.map(|(buttons, _)| buttons).unwrap_or_default()
+    }
+
+    /// Get the POVs as of the last latched packet.
+    pub fn povs(&self) -> Povs {
+        self.previous.map(|(_, povs)| povs).unwrap_or_default()
+    }
+
+    /// Get whether a button is held down as of the last latched packet. Buttons are numbered
+    /// starting at 1.
+    pub fn button(&self, button: usize) -> bool { self.buttons().get(button) }
+
+    fn edge_count(edges: &mut [u32; 32], button: usize) -> Option<&mut u32> {
+        if button == 0 { None } else { edges.get_mut(button - 1) }
+    }
+
+    /// Take one press of a button, returning `false` if there are none left. If a button was
+    /// pressed twice since the last check, this returns `true` twice.
+    pub fn pressed(&mut self, button: usize) -> bool {
+        match Self::edge_count(&mut self.presses, button) {
+            Some(count) if *count > 0 => { *count -= 1; true }
+            _ => false,
+        }
+    }
+
+    /// Take one release of a button, returning `false` if there are none left.
+    pub fn released(&mut self, button: usize) -> bool {
+        match Self::edge_count(&mut self.releases, button) {
+            Some(count) if *count > 0 => { *count -= 1; true }
+            _ => false,
+        }
+    }
+
+    /// Take every press of a button since the last check, returning how many there were.
+    pub fn take_presses(&mut self, button: usize) -> u32 {
+        Self::edge_count(&mut self.presses, button).map_or(0, ::std::mem::take)
+    }
+
+    /// Take every release of a button since the last check, returning how many there were.
+    pub fn take_releases(&mut self, button: usize) -> u32 {
+        Self::edge_count(&mut self.releases, button).map_or(0, ::std::mem::take)
+    }
+
+    /// Take the oldest change of a POV that hasn't been checked yet, returning its new angle.
+    /// The angle is -1 if the POV was let go.
+    pub fn pov_changed(&mut self, pov: usize) -> Option<i16> {
+        self.pov_changes.get_mut(pov).and_then(|changes| changes.pop_front())
+    }
+
+    /// Forget every change that hasn't been checked yet.
+    pub fn clear(&mut self) {
+        self.presses = [0; 32];
+        self.releases = [0; 32];
+        for changes in &mut self.pov_changes { changes.clear(); }
+    }
+}
@@ -8,22 +8,32 @@ pub mod accelerometer;
 pub mod analog_gyro;
 pub mod analog_trigger;
 pub mod analog;
+pub mod can;
 pub mod compressor;
 pub mod counter;
 pub mod dio;
 pub mod driverstation;
 pub mod joystick;
 pub mod encoder;
+pub mod framing;
+pub mod gamepad;
 pub mod i2c;
 pub mod interrupt;
+pub mod motor_safety;
 pub mod notifier;
 pub mod pdp;
 pub mod power;
 pub mod pwm;
 pub mod relay;
+pub mod robot;
 pub mod serial;
+pub mod servo;
+pub mod snapshot;
 pub mod solenoid;
+pub mod speed_controller;
 pub mod spi;
+pub mod threads;
+pub mod time;
 pub mod usage_reporting;
 
 extern "C" {
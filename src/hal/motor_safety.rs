@@ -0,0 +1,238 @@
+//! A watchdog that turns off actuators whose outputs stop being updated, so that a hung loop
+//! can't leave a motor running on its last command.
+
+use error::*;
+use hal::driverstation;
+use hal::time::FpgaInstant;
+use hal::types::Handle;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// A handle along with the function that turns it off
+type Stop = (Handle, fn(Handle) -> HalResult<()>);
+
+/// How to turn off an actuator from the watchdog thread.
+#[derive(Clone, Debug)]
+pub struct StopAction {
+    stops: Vec<Stop>,
+}
+
+impl StopAction {
+    pub(crate) fn new(handle: Handle, stop: fn(Handle) -> HalResult<()>) -> Self {
+        StopAction { stops: vec![(handle, stop)] }
+    }
+
+    /// Combine two actions, for actuators made up of more than one output.
+    pub fn and(mut self, other: StopAction) -> Self {
+        self.stops.extend(other.stops);
+        self
+    }
+
+    fn run(&self) -> HalResult<()> {
+        // Try to stop everything even if one of them fails
+        let mut result = Ok(());
+        for &(handle, stop) in &self.stops {
+            if let Err(err) = stop(handle) { result = Err(err); }
+        }
+        result
+    }
+}
+
+/// An output that can be watched by a `SafetyMonitor`.
+pub trait Actuator {
+    fn stop_action(&self) -> StopAction;
+}
+
+#[derive(Debug)]
+struct Entry {
+    description: String,
+    action: StopAction,
+    expiration: Duration,
+    deadline: Option<FpgaInstant>,
+    enabled: bool,
+    stopped: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    // Held while expired actuators are being stopped, and while an actuator is unregistered,
+    // so that a handle can't be freed while it is in use. Always taken before `entries`.
+    stopping: Mutex<()>,
+    entries: Mutex<HashMap<usize, Entry>>,
+    next_id: AtomicUsize,
+    running: AtomicBool,
+}
+
+impl Shared {
+    fn check(&self) {
+        // Outputs are already off while the robot is disabled
+        match driverstation::get_control_word() {
+            Ok(ref word) if word.enabled() => {},
+            _ => return,
+        }
+
+        let now = match FpgaInstant::now() {
+            Ok(now) => now,
+            Err(_) => return,
+        };
+
+        let stopping = self.stopping.lock().unwrap();
+        let expired: Vec<(String, StopAction)> = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.values_mut()
+                .filter(|entry| entry.enabled && !entry.stopped && match entry.deadline {
+                    Some(deadline) => now >= deadline,
+                    None => true,
+                })
+                .map(|entry| {
+                    entry.stopped = true;
+                    (entry.description.clone(), entry.action.clone())
+                })
+                .collect()
+        };
+
+        // `entries` is unlocked here so that feeding isn't held up, but `stopping` keeps the
+        // actuators from being unregistered until they are stopped
+        let messages: Vec<String> = expired.into_iter().map(|(description, action)| match action.run() {
+            Ok(()) => format!("{}: output not updated often enough, output stopped", description),
+            Err(err) => format!("{}: output not updated often enough, and stopping it failed: {}",
+                                description, err),
+        }).collect();
+        drop(stopping);
+
+        for message in messages {
+            driverstation::send_error(&message, "", "");
+        }
+    }
+}
+
+/// Runs a background thread that stops actuators that haven't been fed within their
+/// expiration time.
+#[derive(Debug)]
+pub struct SafetyMonitor {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SafetyMonitor {
+    /// Start checking every `check_period`. The period should be well under the shortest
+    /// expiration time, 20ms is a good default.
+    pub fn start(check_period: Duration) -> Self {
+        let shared = Arc::new(Shared {
+            stopping: Mutex::new(()),
+            entries: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+            running: AtomicBool::new(true),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("motor safety".into())
+            .spawn(move || {
+                while thread_shared.running.load(Ordering::SeqCst) {
+                    thread_shared.check();
+                    thread::sleep(check_period);
+                }
+            })
+            .expect("failed to spawn motor safety thread");
+
+        SafetyMonitor { shared, thread: Some(thread) }
+    }
+
+    /// Start watching `actuator`, which is stopped if `MotorSafety::feed` isn't called at least
+    /// once every `expiration` while the robot is enabled. The actuator is borrowed for as long
+    /// as it is watched, and starts out expired until it is first fed. `description` is used in
+    /// the error sent to the driver station.
+    pub fn watch<'a, A: Actuator>(&self, actuator: &'a A, expiration: Duration, description: &str) -> MotorSafety<'a> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::SeqCst);
+
+        self.shared.entries.lock().unwrap().insert(id, Entry {
+            description: description.into(),
+            action: actuator.stop_action(),
+            expiration,
+            deadline: None,
+            enabled: true,
+            stopped: false,
+        });
+
+        MotorSafety { shared: self.shared.clone(), id, _actuator: PhantomData }
+    }
+
+    /// Stop the background thread. Actuators that are still watched aren't checked anymore.
+    pub fn stop(mut self) { self.stop_thread(); }
+
+    fn stop_thread(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SafetyMonitor {
+    fn drop(&mut self) { self.stop_thread(); }
+}
+
+/// The registration of an actuator with a `SafetyMonitor`. The actuator stops being watched
+/// when this is dropped.
+#[derive(Debug)]
+pub struct MotorSafety<'a> {
+    shared: Arc<Shared>,
+    id: usize,
+    _actuator: PhantomData<&'a ()>,
+}
+
+impl<'a> MotorSafety<'a> {
+    fn with_entry<T, F: FnOnce(&mut Entry) -> T>(&self, func: F) -> T {
+        let mut entries = self.shared.entries.lock().unwrap();
+        func(entries.get_mut(&self.id).expect("motor safety entry missing"))
+    }
+
+    /// Tell the watchdog that the output was just updated. Should be called along with every
+    /// update to the actuator.
+    pub fn feed(&self) -> HalResult<()> {
+        let now = FpgaInstant::now()?;
+        self.with_entry(|entry| {
+            entry.deadline = Some(now + entry.expiration);
+            entry.stopped = false;
+        });
+        Ok(())
+    }
+
+    pub fn set_expiration(&self, expiration: Duration) {
+        self.with_entry(|entry| entry.expiration = expiration);
+    }
+
+    pub fn expiration(&self) -> Duration {
+        self.with_entry(|entry| entry.expiration)
+    }
+
+    /// Turn checking on or off for this actuator, for example in test mode.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.with_entry(|entry| entry.enabled = enabled);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.with_entry(|entry| entry.enabled)
+    }
+
+    /// Get whether the actuator has been fed recently enough. Actuators that aren't checked
+    /// are always alive.
+    pub fn is_alive(&self) -> HalResult<bool> {
+        let now = FpgaInstant::now()?;
+        Ok(self.with_entry(|entry| {
+            !entry.enabled || (!entry.stopped && entry.deadline.is_some_and(|deadline| now < deadline))
+        }))
+    }
+}
+
+impl<'a> Drop for MotorSafety<'a> {
+    fn drop(&mut self) {
+        let _stopping = self.shared.stopping.lock().unwrap();
+        self.shared.entries.lock().unwrap().remove(&self.id);
+    }
+}
@@ -1,8 +1,11 @@
 use error::*;
+use hal::driverstation;
+use hal::time::micros;
 use hal::types::*;
-use std::thread::{JoinHandle, spawn};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 extern "C" {
     fn HAL_InitializeNotifier(status: *mut i32) -> NotifierHandle;
@@ -10,69 +13,309 @@ extern "C" {
     fn HAL_CleanNotifier(handle: NotifierHandle, status: *mut i32);
     fn HAL_UpdateNotifierAlarm(handle: NotifierHandle, triggerTime: u64, status: *mut i32);
     fn HAL_CancelNotifierAlarm(handle: NotifierHandle, status: *mut i32);
-    fn HAL_WaitForNotifierAlarm(handle: NotifierHandle, status: *mut i32);
+    fn HAL_WaitForNotifierAlarm(handle: NotifierHandle, status: *mut i32) -> u64;
 }
 
+/// A bare HAL notifier, for blocking the current thread until an FPGA timestamp.
 #[derive(Debug)]
+pub struct NotifierAlarm {
+    pub(crate) handle: NotifierHandle,
+}
+
+impl NotifierAlarm {
+    pub fn new() -> HalResult<Self> {
+        let handle = unsafe { hal_call!(HAL_InitializeNotifier())? };
+        Ok(NotifierAlarm { handle })
+    }
+
+    /// Block until the FPGA time reaches `time` microseconds, and return the FPGA time when the
+    /// alarm went off. Returns immediately if `time` has already passed.
+    pub fn wait_until(&self, time: u64) -> HalResult<u64> {
+        unsafe {
+            hal_call!(HAL_UpdateNotifierAlarm(self.handle, time))?;
+            hal_call!(HAL_WaitForNotifierAlarm(self.handle))
+        }
+    }
+}
+
+impl Drop for NotifierAlarm {
+    fn drop(&mut self) {
+        unsafe {
+            HAL_StopNotifier(self.handle, ::std::ptr::null_mut());
+            HAL_CleanNotifier(self.handle, ::std::ptr::null_mut());
+        }
+    }
+}
+
+/// What a periodic `Notifier` does when the handler falls behind by one or more periods.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MissedPeriods {
+    /// Call the handler once, and continue with the next period that hasn't passed yet.
+    Skip,
+    /// Call the handler once for every missed period, back to back.
+    CatchUp,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Alarm {
+    Idle,
+    Once(u64),
+    Periodic { next: u64, period: u64 },
+}
+
+// What the worker does with the HAL alarm after handling one going off.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Rearm {
+    At(u64),
+    // Nothing is scheduled. The alarm still has to be pushed out to the end of time, since only
+    // updating it clears the last trigger, and waiting again would return straight away.
+    Park,
+}
+
+impl Rearm {
+    fn trigger_time(self) -> u64 {
+        match self {
+            Rearm::At(time) => time,
+            Rearm::Park => u64::MAX,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    alarm: Alarm,
+    missed: MissedPeriods,
+}
+
+impl State {
+    // Decide whether an alarm going off at `now` should call the handler, and when the next
+    // alarm should be. Alarms that went off after being cancelled or moved are ignored here,
+    // which is what keeps `cancel` from racing the worker thread.
+    fn advance(&mut self, now: u64) -> (bool, Rearm) {
+        match self.alarm {
+            Alarm::Idle => (false, Rearm::Park),
+            Alarm::Once(deadline) if now < deadline => (false, Rearm::At(deadline)),
+            Alarm::Once(_) => {
+                self.alarm = Alarm::Idle;
+                (true, Rearm::Park)
+            }
+            Alarm::Periodic { next, .. } if now < next => (false, Rearm::At(next)),
+            Alarm::Periodic { next, period } => {
+                // Deadlines are stepped from the previous deadline rather than from `now`, so
+                // the schedule doesn't drift
+                let next = match self.missed {
+                    MissedPeriods::CatchUp => next.saturating_add(period),
+                    MissedPeriods::Skip => next.saturating_add(((now - next) / period + 1).saturating_mul(period)),
+                };
+                self.alarm = Alarm::Periodic { next, period };
+                (true, Rearm::At(next))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    handle: NotifierHandle,
+    state: Mutex<State>,
+}
+
+/// Calls a handler on its own thread at times kept by the FPGA, either periodically or once.
+///
+/// HAL errors on the notifier thread are passed to an error handler instead of panicking. A
+/// failed wait ends the thread, and a failed alarm update cancels the alarm. If the alarm can't
+/// even be parked once nothing is scheduled, the thread ends too.
 pub struct Notifier {
-    pub(crate) handle: Handle,
-    thread_running: Arc<AtomicBool>,
-    period: Arc<AtomicUsize>,
+    shared: Arc<Shared>,
     notifier_thread: Option<JoinHandle<()>>,
 }
 
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("handle", &self.shared.handle)
+            .field("state", &self.shared.state)
+            .finish()
+    }
+}
+
 impl Notifier {
-    pub fn new<F: Fn() + Send + 'static>(handler: F, period: usize) -> HalResult<Self> {
-        // Initialize a notifier
+    /// Create a notifier that reports errors to the driver station. No alarm is set until
+    /// `start_periodic` or `start_single` is called.
+    pub fn new<F>(handler: F) -> HalResult<Self>
+        where F: FnMut() + Send + 'static
+    {
+        Notifier::with_error_handler(handler, |err| {
+            driverstation::send_error(&format!("Notifier error: {}", err), "", "");
+        })
+    }
+
+    /// Create a notifier that sends errors down a channel.
+    pub fn with_channel<F>(handler: F) -> HalResult<(Self, mpsc::Receiver<HalError>)>
+        where F: FnMut() + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel();
+        let notifier = Notifier::with_error_handler(handler, move |err| { let _ = sender.send(err); })?;
+        Ok((notifier, receiver))
+    }
+
+    /// Create a notifier that calls `on_error` with errors from the notifier thread.
+    pub fn with_error_handler<F, E>(mut handler: F, mut on_error: E) -> HalResult<Self>
+        where F: FnMut() + Send + 'static,
+              E: FnMut(HalError) + Send + 'static
+    {
         let handle = unsafe { hal_call!(HAL_InitializeNotifier())? };
-        let period = Arc::new(AtomicUsize::new(period));
-        let thread_running = Arc::new(AtomicBool::new(true));
+        let shared = Arc::new(Shared {
+            handle,
+            state: Mutex::new(State { alarm: Alarm::Idle, missed: MissedPeriods::Skip }),
+        });
 
-        // Start the listener thread
-        let thread_condition = thread_running.clone();
-        let thread_period = period.clone();
-        let notifier_thread = Some(spawn(move || {
-            let mut timeout = ::hal::get_fpga_time().expect("Could not read FPGA time.");
+        let thread_shared = shared.clone();
+        let notifier_thread = Some(thread::spawn(move || loop {
+            // Returns 0 once the notifier is stopped
+            let now = match unsafe { hal_call!(HAL_WaitForNotifierAlarm(thread_shared.handle)) } {
+                Ok(0) => return,
+                Ok(now) => now,
+                Err(err) => return on_error(err),
+            };
 
-            while thread_condition.load(Ordering::SeqCst) {
-                timeout += thread_period.load(Ordering::SeqCst) as u64;
+            let (fire, rearm, error) = {
+                let mut state = thread_shared.state.lock().unwrap();
+                let (fire, rearm) = state.advance(now);
+                let error = unsafe {
+                    hal_call!(HAL_UpdateNotifierAlarm(thread_shared.handle, rearm.trigger_time())).err()
+                };
 
-                unsafe {
-                    // Update the timer and then wait for it. Unwrap because we're kinda screwed anyways if this fails
-                    hal_call!(HAL_UpdateNotifierAlarm(handle, timeout)).expect("Updating notifier failed.");
-                    hal_call!(HAL_WaitForNotifierAlarm(handle)).expect("Waiting for notifier alarm failed.");
-                }
+                if error.is_some() { state.alarm = Alarm::Idle; }
+                (fire, rearm, error)
+            };
 
-                handler();
+            if fire { handler(); }
+            if let Some(err) = error {
+                on_error(err);
+                // An alarm that can't be parked keeps going off with the old trigger time
+                if rearm == Rearm::Park { return; }
             }
         }));
 
-        Ok(Notifier { handle, notifier_thread, thread_running, period })
+        Ok(Notifier { shared, notifier_thread })
     }
 
-    pub fn set_period(&self, period: usize) {
-        self.period.store(period, Ordering::SeqCst);
+    // Must be called with the state locked, so the worker sees the alarm and the state change
+    // together.
+    fn set_alarm(&self, state: &mut State, alarm: Alarm) -> HalResult<()> {
+        state.alarm = alarm;
+        let result = unsafe {
+            match alarm {
+                Alarm::Idle => hal_call!(HAL_CancelNotifierAlarm(self.shared.handle)),
+                Alarm::Once(deadline) | Alarm::Periodic { next: deadline, .. } =>
+                    hal_call!(HAL_UpdateNotifierAlarm(self.shared.handle, deadline)),
+            }
+        };
+
+        if result.is_err() { state.alarm = Alarm::Idle; }
+        result
     }
 
-    pub fn cancel_alarm(&self) -> HalResult<()> {
-        unsafe { hal_call!(HAL_CancelNotifierAlarm(self.handle)) }
+    /// Call the handler every `period`, starting one period from now. Replaces any alarm that
+    /// was already set. Panics if `period` is shorter than a microsecond.
+    pub fn start_periodic(&self, period: Duration) -> HalResult<()> {
+        let period = micros(period);
+        assert!(period > 0, "Notifier period must be at least 1us");
+
+        let mut state = self.shared.state.lock().unwrap();
+        let next = ::hal::get_fpga_time()?.saturating_add(period);
+        self.set_alarm(&mut state, Alarm::Periodic { next, period })
     }
-}
 
-impl Drop for Notifier {
-    fn drop(&mut self) {
+    /// Call the handler once, `delay` from now. Replaces any alarm that was already set.
+    pub fn start_single(&self, delay: Duration) -> HalResult<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        let deadline = ::hal::get_fpga_time()?.saturating_add(micros(delay));
+        self.set_alarm(&mut state, Alarm::Once(deadline))
+    }
+
+    /// Set what happens when a periodic handler falls behind. The default is
+    /// `MissedPeriods::Skip`.
+    pub fn set_missed_periods(&self, missed: MissedPeriods) {
+        self.shared.state.lock().unwrap().missed = missed;
+    }
+
+    /// Cancel the current alarm. Once this returns the handler won't be called again until a
+    /// new alarm is started, though a call that already began may still be running.
+    pub fn cancel(&self) -> HalResult<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.set_alarm(&mut state, Alarm::Idle)
+    }
+
+    /// Stop the notifier, waiting for a running handler call to finish. Returns `false` if the
+    /// handler or the error handler panicked.
+    pub fn stop(mut self) -> bool { self.shut_down() }
+
+    fn shut_down(&mut self) -> bool {
+        let thread = match self.notifier_thread.take() {
+            Some(thread) => thread,
+            None => return true,
+        };
+
         // Neither stop nor clean set the status param.
         // XXX: probably going to be changed in 2019
-        unsafe { HAL_StopNotifier(self.handle, ::std::ptr::null_mut()); }
-        self.thread_running.store(false, Ordering::SeqCst);
-        // `self.notifier_thread` is only ever `None` after this call. `self.notifier_thread` is
-        // only `Option` because of this call. Since joining takes ownership of the JoinHandle
-        // and we are provided a `&mut self`, trying to join normally would make the borrow
-        // checker complain.
-        // We discard any error here because the only thing we care about is disallowing calls
-        // to `HAL_WaitForNotifierAlarm` after the notifier has been cleaned up, and a panicked
-        // thread means it's halted.
-        let _ = self.notifier_thread.take().unwrap().join();
-        unsafe { HAL_CleanNotifier(self.handle, ::std::ptr::null_mut()) }
+        unsafe { HAL_StopNotifier(self.shared.handle, ::std::ptr::null_mut()); }
+        // Stopping makes `HAL_WaitForNotifierAlarm` return 0, which ends the thread. The
+        // notifier can only be cleaned up once nothing is waiting on it anymore.
+        let succeeded = thread.join().is_ok();
+        unsafe { HAL_CleanNotifier(self.shared.handle, ::std::ptr::null_mut()); }
+        succeeded
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) { self.shut_down(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(alarm: Alarm, missed: MissedPeriods) -> State {
+        State { alarm, missed }
+    }
+
+    #[test]
+    fn idle_parks() {
+        let mut state = state(Alarm::Idle, MissedPeriods::Skip);
+        assert_eq!(state.advance(100), (false, Rearm::Park));
+        assert_eq!(state.alarm, Alarm::Idle);
+    }
+
+    #[test]
+    fn once_fires_then_parks() {
+        let mut state = state(Alarm::Once(100), MissedPeriods::Skip);
+        assert_eq!(state.advance(50), (false, Rearm::At(100)));
+        assert_eq!(state.advance(100), (true, Rearm::Park));
+        assert_eq!(state.alarm, Alarm::Idle);
+        // A stale trigger after the one shot went off must not fire again
+        assert_eq!(state.advance(100), (false, Rearm::Park));
+    }
+
+    #[test]
+    fn periodic_skips_missed_periods() {
+        let mut state = state(Alarm::Periodic { next: 100, period: 10 }, MissedPeriods::Skip);
+        assert_eq!(state.advance(99), (false, Rearm::At(100)));
+        assert_eq!(state.advance(135), (true, Rearm::At(140)));
+        assert_eq!(state.alarm, Alarm::Periodic { next: 140, period: 10 });
+    }
+
+    #[test]
+    fn periodic_catches_up() {
+        let mut state = state(Alarm::Periodic { next: 100, period: 10 }, MissedPeriods::CatchUp);
+        assert_eq!(state.advance(135), (true, Rearm::At(110)));
+        assert_eq!(state.advance(135), (true, Rearm::At(120)));
+    }
+
+    #[test]
+    fn park_never_triggers() {
+        assert_eq!(Rearm::Park.trigger_time(), u64::MAX);
+        assert_eq!(Rearm::At(42).trigger_time(), 42);
     }
 }
@@ -1,4 +1,5 @@
 use error::*;
+use hal::motor_safety::{Actuator, StopAction};
 use hal::types::*;
 use std::os::raw::*;
 
@@ -35,6 +36,44 @@ extern "C" {
     fn HAL_GetLoopTiming(status: *mut i32) -> i32;
 }
 
+// Constants from the HAL, used to convert between raw and microsecond PWM configurations
+const DEFAULT_PWM_CENTER_MICROS: f64 = 1500.0;
+const DEFAULT_PWM_STEPS_DOWN: i32 = 1000;
+
+fn check_channel(channel: i32) -> bool { unsafe { HAL_CheckPWMChannel(channel) != 0 } }
+
+/// Get the number of system clock ticks in one PWM cycle.
+pub fn get_loop_timing() -> HalResult<i32> {
+    unsafe { hal_call!(HAL_GetLoopTiming()) }
+}
+
+/// The length of one raw PWM step, in microseconds.
+fn loop_time_micros() -> HalResult<f64> {
+    Ok(get_loop_timing()? as f64 / ::hal::system_clock_ticks_per_microsecond() as f64)
+}
+
+/// How often the PWM signal is sent. Slower devices like servos need a longer period.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PeriodScale {
+    /// Send every pulse
+    Scale1X,
+    /// Send every other pulse
+    Scale2X,
+    /// Send every fourth pulse
+    Scale4X,
+}
+
+impl PeriodScale {
+    fn squelch_mask(&self) -> i32 {
+        match *self {
+            PeriodScale::Scale1X => 0,
+            PeriodScale::Scale2X => 1,
+            PeriodScale::Scale4X => 3,
+        }
+    }
+}
+
+/// PWM pulse bounds in raw FPGA units.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PwmConfig {
     pub max_pwm: i32,
@@ -44,20 +83,146 @@ pub struct PwmConfig {
     pub min_pwm: i32,
 }
 
+/// PWM pulse bounds in microseconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PwmConfigMicros {
+    pub max: f64,
+    pub deadband_max: f64,
+    pub center: f64,
+    pub deadband_min: f64,
+    pub min: f64,
+}
+
 #[derive(Debug)]
 pub struct Pwm {
     pub(crate) handle: Handle,
+    channel: i32,
 }
 
 impl Pwm {
-    fn initialize(port: PortHandle) -> HalResult<Self> {
-        unsafe { hal_call!(HAL_InitializePWMPort(port)).map(|handle| Pwm { handle }) }
+    pub fn new(channel: i32) -> HalResult<Self> {
+        if !check_channel(channel) { return Err(HalError::InvalidChannel(channel)); }
+        let port = ::hal::get_port(channel).ok_or(HalError::InvalidChannel(channel))?;
+        let handle = unsafe { hal_call!(HAL_InitializePWMPort(port))? };
+
+        let pwm = Pwm { handle, channel };
+        pwm.set_disabled()?;
+        pwm.set_eliminate_deadband(false)?;
+        Ok(pwm)
+    }
+
+    pub fn channel(&self) -> i32 { self.channel }
+
+    /// Set the output as a speed between -1 and 1, scaled between the configured bounds.
+    pub fn set_speed(&self, speed: f64) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetPWMSpeed(self.handle, speed)) }
+    }
+
+    pub fn get_speed(&self) -> HalResult<f64> {
+        unsafe { hal_call!(HAL_GetPWMSpeed(self.handle)) }
+    }
+
+    /// Set the output as a position between 0 and 1, scaled between the configured bounds.
+    pub fn set_position(&self, position: f64) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetPWMPosition(self.handle, position)) }
+    }
+
+    pub fn get_position(&self) -> HalResult<f64> {
+        unsafe { hal_call!(HAL_GetPWMPosition(self.handle)) }
+    }
+
+    /// Set the raw pulse value sent to the FPGA.
+    pub fn set_raw(&self, value: i32) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetPWMRaw(self.handle, value)) }
     }
+
+    pub fn get_raw(&self) -> HalResult<i32> {
+        unsafe { hal_call!(HAL_GetPWMRaw(self.handle)) }
+    }
+
+    /// Stop sending pulses. Most motor controllers treat this as a neutral output.
+    pub fn set_disabled(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetPWMDisabled(self.handle)) }
+    }
+
+    /// Briefly hold the output at zero, which some motor controllers need after a reset.
+    pub fn latch_zero(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_LatchPWMZero(self.handle)) }
+    }
+
+    pub fn set_period_scale(&self, scale: PeriodScale) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetPWMPeriodScale(self.handle, scale.squelch_mask())) }
+    }
+
+    /// When enabled, speeds inside the deadband are sent as the center value instead of being
+    /// scaled across the whole range.
+    pub fn set_eliminate_deadband(&self, eliminate: bool) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetPWMEliminateDeadband(self.handle, eliminate as NativeBool)) }
+    }
+
+    pub fn get_eliminate_deadband(&self) -> HalResult<bool> {
+        unsafe { hal_call!(HAL_GetPWMEliminateDeadband(self.handle)).map(|n| n != 0) }
+    }
+
+    pub fn set_config_raw(&self, config: PwmConfig) -> HalResult<()> {
+        unsafe {
+            hal_call!(HAL_SetPWMConfigRaw(self.handle, config.max_pwm, config.deadband_max_pwm,
+                                          config.center_pwm, config.deadband_min_pwm, config.min_pwm))
+        }
+    }
+
+    pub fn get_config_raw(&self) -> HalResult<PwmConfig> {
+        let mut config = PwmConfig { max_pwm: 0, deadband_max_pwm: 0, center_pwm: 0, deadband_min_pwm: 0, min_pwm: 0 };
+        unsafe {
+            hal_call!(HAL_GetPWMConfigRaw(self.handle, &mut config.max_pwm, &mut config.deadband_max_pwm,
+                                          &mut config.center_pwm, &mut config.deadband_min_pwm,
+                                          &mut config.min_pwm))?;
+        }
+        Ok(config)
+    }
+
+    pub fn set_config_micros(&self, config: PwmConfigMicros) -> HalResult<()> {
+        // The HAL takes the bounds in milliseconds
+        unsafe {
+            hal_call!(HAL_SetPWMConfig(self.handle, config.max / 1000.0, config.deadband_max / 1000.0,
+                                       config.center / 1000.0, config.deadband_min / 1000.0,
+                                       config.min / 1000.0))
+        }
+    }
+
+    /// The HAL only stores raw bounds, so this converts them back into microseconds using the
+    /// current loop timing.
+    pub fn get_config_micros(&self) -> HalResult<PwmConfigMicros> {
+        let raw = self.get_config_raw()?;
+        let loop_time = loop_time_micros()?;
+        // Inverse of the HAL's conversion from milliseconds to raw values
+        let to_micros = |raw: i32| (raw - (DEFAULT_PWM_STEPS_DOWN - 1)) as f64 * loop_time + DEFAULT_PWM_CENTER_MICROS;
+
+        Ok(PwmConfigMicros {
+            max: to_micros(raw.max_pwm),
+            deadband_max: to_micros(raw.deadband_max_pwm),
+            center: to_micros(raw.center_pwm),
+            deadband_min: to_micros(raw.deadband_min_pwm),
+            min: to_micros(raw.min_pwm),
+        })
+    }
+}
+
+fn stop(handle: Handle) -> HalResult<()> {
+    unsafe { hal_call!(HAL_SetPWMDisabled(handle)) }
+}
+
+impl Actuator for Pwm {
+    fn stop_action(&self) -> StopAction { StopAction::new(self.handle, stop) }
 }
 
 impl Drop for Pwm {
     fn drop(&mut self) {
-        // Ok this one can fail, but what are we supposed to do other than panic here?
-        // unsafe { HAL_FreePWMPort(self.handle) }
+        // We own a valid handle, so neither of these can fail
+        let mut status = 0;
+        unsafe {
+            HAL_SetPWMDisabled(self.handle, &mut status);
+            HAL_FreePWMPort(self.handle, &mut status);
+        }
     }
 }
@@ -1,4 +1,5 @@
 use error::*;
+use hal::motor_safety::{Actuator, StopAction};
 use hal::types::{Handle, PortHandle, RelayHandle, NativeBool};
 
 extern "C" {
@@ -35,6 +36,14 @@ impl Relay {
     }
 }
 
+fn stop(handle: Handle) -> HalResult<()> {
+    unsafe { hal_call!(HAL_SetRelay(handle, 0)) }
+}
+
+impl Actuator for Relay {
+    fn stop_action(&self) -> StopAction { StopAction::new(self.handle, stop) }
+}
+
 impl Drop for Relay {
     fn drop(&mut self) {
         unsafe { HAL_FreeRelayPort(self.handle); }
@@ -90,6 +99,12 @@ impl DoubleRelay {
     }
 }
 
+impl Actuator for DoubleRelay {
+    fn stop_action(&self) -> StopAction {
+        self.forward.stop_action().and(self.reverse.stop_action())
+    }
+}
+
 // TODO: do we actually want all this?
 /// Practically the equivalent the the wpilib Relay class in get/set behavior
 #[derive(Debug, Eq, PartialEq, Hash)]
@@ -129,3 +144,12 @@ impl AnyRelay {
         }
     }
 }
+
+impl Actuator for AnyRelay {
+    fn stop_action(&self) -> StopAction {
+        match *self {
+            AnyRelay::Both(ref double) => double.stop_action(),
+            AnyRelay::Forward(ref relay) | AnyRelay::Reverse(ref relay) => relay.stop_action(),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! A framework for running robot code, which calls into user code whenever the robot changes
+//! modes and once every driver station packet.
+
+use error::*;
+use hal::driverstation::{self, ControlWord};
+use hal::notifier::NotifierAlarm;
+use hal::time::micros;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The mode the driver station has the robot in.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum RobotMode {
+    Disabled,
+    Autonomous,
+    Teleop,
+    Test,
+}
+
+impl RobotMode {
+    /// The prefix of this mode's `Robot` hooks, like `teleop` for `teleop_init`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RobotMode::Disabled => "disabled",
+            RobotMode::Autonomous => "autonomous",
+            RobotMode::Teleop => "teleop",
+            RobotMode::Test => "test",
+        }
+    }
+
+    // Full names of this mode's hooks, for loop overrun warnings
+    fn init_hook(&self) -> &'static str {
+        match *self {
+            RobotMode::Disabled => "disabled_init",
+            RobotMode::Autonomous => "autonomous_init",
+            RobotMode::Teleop => "teleop_init",
+            RobotMode::Test => "test_init",
+        }
+    }
+
+    fn periodic_hook(&self) -> &'static str {
+        match *self {
+            RobotMode::Disabled => "disabled_periodic",
+            RobotMode::Autonomous => "autonomous_periodic",
+            RobotMode::Teleop => "teleop_periodic",
+            RobotMode::Test => "test_periodic",
+        }
+    }
+
+    pub fn from_control_word(word: &ControlWord) -> Self {
+        if !word.enabled() || !word.ds_attached() {
+            RobotMode::Disabled
+        } else if word.test() {
+            RobotMode::Test
+        } else if word.autonomous() {
+            RobotMode::Autonomous
+        } else {
+            RobotMode::Teleop
+        }
+    }
+
+    /// Tell the driver station that the robot code is running in this mode. The driver
+    /// station shows no robot code unless this is called regularly.
+    pub fn observe(&self) {
+        match *self {
+            RobotMode::Disabled => driverstation::observe_disabled(),
+            RobotMode::Autonomous => driverstation::observe_autonomous(),
+            RobotMode::Teleop => driverstation::observe_teleop(),
+            RobotMode::Test => driverstation::observe_test(),
+        }
+    }
+}
+
+/// User robot code. Every method does nothing by default.
+///
+/// `*_init` is called once when the robot enters a mode, and `*_periodic` is called on every
+/// loop while the robot is in that mode. `robot_periodic` is called on every loop after the
+/// mode specific one.
+pub trait Robot {
+    /// Called once, before the driver station is told that the robot code is ready.
+    fn robot_init(&mut self) {}
+    fn robot_periodic(&mut self) {}
+
+    fn disabled_init(&mut self) {}
+    fn disabled_periodic(&mut self) {}
+
+    fn autonomous_init(&mut self) {}
+    fn autonomous_periodic(&mut self) {}
+
+    fn teleop_init(&mut self) {}
+    fn teleop_periodic(&mut self) {}
+
+    fn test_init(&mut self) {}
+    fn test_periodic(&mut self) {}
+}
+
+/// Calls the hooks of a `Robot` as the driver station changes modes.
+#[derive(Debug)]
+pub struct RobotRunner<R> {
+    robot: R,
+    mode: Option<RobotMode>,
+}
+
+impl<R: Robot> RobotRunner<R> {
+    pub fn new(robot: R) -> Self {
+        RobotRunner { robot, mode: None }
+    }
+
+    pub fn robot(&self) -> &R { &self.robot }
+
+    pub fn robot_mut(&mut self) -> &mut R { &mut self.robot }
+
+    /// Get the mode of the last loop, or `None` before the first one.
+    pub fn mode(&self) -> Option<RobotMode> { self.mode }
+
+    /// Call `robot_init` and tell the driver station that the robot code is ready.
+    pub fn init(&mut self) {
+        self.robot.robot_init();
+        driverstation::observe_starting();
+    }
+
+    /// Run one loop for the mode described by `word`. The mode's init hook is called first if
+    /// the mode changed since the last loop.
+    pub fn step(&mut self, word: &ControlWord) {
+        let mode = RobotMode::from_control_word(word);
+        if self.mode != Some(mode) {
+            self.enter_mode(mode);
+        }
+
+        mode.observe();
+        self.periodic(mode);
+        self.robot.robot_periodic();
+    }
+
+    pub(crate) fn enter_mode(&mut self, mode: RobotMode) {
+        self.mode = Some(mode);
+        match mode {
+            RobotMode::Disabled => self.robot.disabled_init(),
+            RobotMode::Autonomous => self.robot.autonomous_init(),
+            RobotMode::Teleop => self.robot.teleop_init(),
+            RobotMode::Test => self.robot.test_init(),
+        }
+    }
+
+    pub(crate) fn periodic(&mut self, mode: RobotMode) {
+        match mode {
+            RobotMode::Disabled => self.robot.disabled_periodic(),
+            RobotMode::Autonomous => self.robot.autonomous_periodic(),
+            RobotMode::Teleop => self.robot.teleop_periodic(),
+            RobotMode::Test => self.robot.test_periodic(),
+        }
+    }
+
+    /// Run a loop for every driver station packet, forever. If no packet arrives for 100ms,
+    /// a loop is run anyway so that the driver station keeps seeing the robot code.
+    pub fn run(&mut self) -> ! {
+        loop {
+            driverstation::wait_for_data(Some(0.1));
+            let word = driverstation::get_control_word().unwrap_or_default();
+            self.step(&word);
+        }
+    }
+}
+
+/// Initialize the HAL and run `robot` with a loop for every driver station packet. This only
+/// returns if the HAL fails to initialize.
+pub fn run_robot<R: Robot>(robot: R) -> HalResult<()> {
+    if !::hal::hal_initialize(500, 0) {
+        return Err(HalError::Other("Failed to initialize the HAL".into()));
+    }
+
+    let mut runner = RobotRunner::new(robot);
+    runner.init();
+    runner.run()
+}
+
+/// Runs a `Robot` at a fixed period, timed by the FPGA instead of driver station packets.
+///
+/// Loops are scheduled at fixed FPGA times, so a slow loop doesn't push back the ones after
+/// it. If a loop takes long enough to miss whole periods, those periods are skipped. Every
+/// loop that takes longer than the period sends a warning to the driver station with the time
+/// spent in each hook.
+#[derive(Debug)]
+pub struct TimedRobot<R> {
+    runner: RobotRunner<R>,
+    period: u64,
+    alarm: NotifierAlarm,
+    // Time spent in each hook during the current loop, in microseconds
+    timings: Vec<(&'static str, u64)>,
+}
+
+impl<R: Robot> TimedRobot<R> {
+    /// Create a runner with a loop every `period`. 20ms is the usual period. Panics if
+    /// `period` is shorter than a microsecond.
+    pub fn new(robot: R, period: Duration) -> HalResult<Self> {
+        let period = micros(period);
+        assert!(period > 0, "TimedRobot period must be at least 1us");
+
+        Ok(TimedRobot {
+            runner: RobotRunner::new(robot),
+            period,
+            alarm: NotifierAlarm::new()?,
+            timings: Vec::with_capacity(3),
+        })
+    }
+
+    pub fn runner(&self) -> &RobotRunner<R> { &self.runner }
+
+    pub fn runner_mut(&mut self) -> &mut RobotRunner<R> { &mut self.runner }
+
+    pub fn period(&self) -> Duration { Duration::from_micros(self.period) }
+
+    fn timed<F: FnOnce(&mut RobotRunner<R>)>(&mut self, name: &'static str, func: F) -> HalResult<()> {
+        let start = ::hal::get_fpga_time()?;
+        func(&mut self.runner);
+        let end = ::hal::get_fpga_time()?;
+        self.timings.push((name, end.saturating_sub(start)));
+        Ok(())
+    }
+
+    /// Run one loop in the mode the driver station has the robot in, and send a warning if the
+    /// whole loop, not just the hooks, took longer than the period.
+    pub fn step(&mut self) -> HalResult<()> {
+        let start = ::hal::get_fpga_time()?;
+        self.timings.clear();
+
+        let word = driverstation::get_control_word().unwrap_or_default();
+        let mode = RobotMode::from_control_word(&word);
+        if self.runner.mode() != Some(mode) {
+            self.timed(mode.init_hook(), |runner| runner.enter_mode(mode))?;
+        }
+
+        mode.observe();
+        self.timed(mode.periodic_hook(), |runner| runner.periodic(mode))?;
+        self.timed("robot_periodic", |runner| runner.robot_mut().robot_periodic())?;
+
+        let total = ::hal::get_fpga_time()?.saturating_sub(start);
+        if total > self.period {
+            let hooks: u64 = self.timings.iter().map(|&(_, time)| time).sum();
+            let mut message = format!("Loop time of {:.3}s overrun ({:.3}s)",
+                                      self.period as f64 / 1e6, total as f64 / 1e6);
+            for &(name, time) in &self.timings {
+                let _ = write!(message, "\n\t{}: {:.6}s", name, time as f64 / 1e6);
+            }
+            let _ = write!(message, "\n\toutside hooks: {:.6}s", total.saturating_sub(hooks) as f64 / 1e6);
+            driverstation::send_warning(&message);
+        }
+
+        Ok(())
+    }
+
+    /// Call `robot_init`, then run loops forever. This only returns if reading the FPGA time
+    /// or waiting on the alarm fails.
+    pub fn run(&mut self) -> HalResult<()> {
+        self.runner.init();
+        let mut deadline = ::hal::get_fpga_time()?.saturating_add(self.period);
+
+        loop {
+            self.alarm.wait_until(deadline)?;
+            self.step()?;
+
+            deadline = deadline.saturating_add(self.period);
+            let now = ::hal::get_fpga_time()?;
+            if now >= deadline {
+                // Skip the periods that were missed, but stay on the original schedule
+                deadline += ((now - deadline) / self.period + 1) * self.period;
+            }
+        }
+    }
+}
+
+/// Initialize the HAL and run `robot` with a loop every `period`. This only returns on error.
+pub fn run_timed_robot<R: Robot>(robot: R, period: Duration) -> HalResult<()> {
+    if !::hal::hal_initialize(500, 0) {
+        return Err(HalError::Other("Failed to initialize the HAL".into()));
+    }
+
+    TimedRobot::new(robot, period)?.run()
+}
@@ -1,5 +1,8 @@
 use error::*;
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
 use std::os::raw::*;
+use std::time::Duration;
 
 extern "C" {
     fn HAL_InitializeSerialPort(port: Port, status: *mut i32);
@@ -20,6 +23,31 @@ extern "C" {
     fn HAL_FlushSerial(port: Port, status: *mut i32);
     fn HAL_ClearSerial(port: Port, status: *mut i32);
     fn HAL_CloseSerial(port: Port, status: *mut i32);
+
+    fn HAL_InitializeOSSerialPort(port: Port, status: *mut i32);
+    fn HAL_SetOSSerialBaudRate(port: Port, baud: i32, status: *mut i32);
+    fn HAL_SetOSSerialDataBits(port: Port, bits: i32, status: *mut i32);
+    fn HAL_SetOSSerialParity(port: Port, parity: i32, status: *mut i32);
+    fn HAL_SetOSSerialStopBits(port: Port, stopBits: i32, status: *mut i32);
+    // The OS versions of write mode, flow control, termination and buffer sizes are empty
+    // stubs in the HAL, so they aren't declared here. See `Serial::require_visa`.
+    fn HAL_SetOSSerialTimeout(port: Port, timeout: c_double, status: *mut i32);
+    fn HAL_GetOSSerialBytesReceived(port: Port, status: *mut i32) -> i32;
+    fn HAL_ReadOSSerial(port: Port, buffer: *mut c_char, count: i32, status: *mut i32) -> i32;
+    fn HAL_WriteOSSerial(port: Port, buffer: *const c_char, count: i32, status: *mut i32) -> i32;
+    fn HAL_FlushOSSerial(port: Port, status: *mut i32);
+    fn HAL_ClearOSSerial(port: Port, status: *mut i32);
+    fn HAL_CloseOSSerial(port: Port, status: *mut i32);
+}
+
+/// Call either the VISA or OS version of a serial function, depending on the port's backend
+macro_rules! serial_call {
+    ($serial:expr, $visa:ident | $os:ident($($arg:expr),*)) => {
+        match $serial.backend {
+            Backend::Visa => hal_call!($visa($serial.port $(, $arg)*)),
+            Backend::Os => hal_call!($os($serial.port $(, $arg)*)),
+        }
+    };
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -65,89 +93,242 @@ pub enum WriteMode {
     FlushWhenFull = 2,
 }
 
+/// The status VISA reports when a read or write times out
+const VI_ERROR_TMO: i32 = -1073807339;
+
+/// Size of the buffer used by the `BufRead` implementation
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// The driver used to talk to a serial port.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Default)]
+pub enum Backend {
+    /// NI-VISA. This is what WPILib uses by default.
+    #[default]
+    Visa,
+    /// The Linux tty driver. Some USB-serial adapters only work properly with this backend.
+    Os,
+}
+
+/// A serial port. Reads and writes go through the `std::io` traits; reads that time out
+/// return an error of kind `ErrorKind::TimedOut`.
 #[derive(Debug)]
 pub struct Serial {
     pub(crate) port: Port,
+    backend: Backend,
+    closed: bool,
+    // Backing storage for `BufRead`. `buffer[position..filled]` has not been consumed yet.
+    buffer: Box<[u8]>,
+    position: usize,
+    filled: usize,
 }
 
 impl Serial {
+    /// Open a serial port using the VISA backend.
     pub fn initialize(port: Port) -> HalResult<Self> {
-        unsafe { hal_call!(HAL_InitializeSerialPort(port))?; }
+        Serial::with_backend(port, Backend::Visa)
+    }
+
+    pub fn with_backend(port: Port, backend: Backend) -> HalResult<Self> {
+        unsafe {
+            match backend {
+                Backend::Visa => hal_call!(HAL_InitializeSerialPort(port))?,
+                Backend::Os => hal_call!(HAL_InitializeOSSerialPort(port))?,
+            }
+        }
 
-        Ok(Serial { port })
+        Ok(Serial {
+            port,
+            backend,
+            closed: false,
+            buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            position: 0,
+            filled: 0,
+        })
     }
 
+    pub fn backend(&self) -> Backend { self.backend }
+
     pub fn set_baud_rate(&self, rate: i32) -> HalResult<()> {
-        unsafe { hal_call!(HAL_SetSerialBaudRate(self.port, rate)) }
+        unsafe { serial_call!(self, HAL_SetSerialBaudRate | HAL_SetOSSerialBaudRate(rate)) }
     }
 
     /// Set the amount of data bits per transfer. Valid values are between 5 and 8.
     pub fn set_data_bits(&self, bits: i32) -> HalResult<()> {
         require_value_between!(bits, 5, 8);
-        unsafe { hal_call!(HAL_SetSerialDataBits(self.port, bits)) }
+        unsafe { serial_call!(self, HAL_SetSerialDataBits | HAL_SetOSSerialDataBits(bits)) }
     }
 
     pub fn set_parity(&self, parity: Parity) -> HalResult<()> {
-        unsafe { hal_call!(HAL_SetSerialParity(self.port, parity as i32)) }
+        unsafe { serial_call!(self, HAL_SetSerialParity | HAL_SetOSSerialParity(parity as i32)) }
     }
 
     pub fn set_stop_bits(&self, bits: StopBits) -> HalResult<()> {
-        unsafe { hal_call!(HAL_SetSerialStopBits(self.port, bits as i32)) }
+        unsafe { serial_call!(self, HAL_SetSerialStopBits | HAL_SetOSSerialStopBits(bits as i32)) }
+    }
+
+    // The OS backend silently ignores some settings, so report those as errors instead
+    fn require_visa(&self, setting: &str) -> HalResult<()> {
+        match self.backend {
+            Backend::Visa => Ok(()),
+            Backend::Os => Err(HalError::Other(format!("{} is not supported by the OS serial backend", setting).into())),
+        }
     }
 
+    /// Not supported by `Backend::Os`.
     pub fn set_write_mode(&self, mode: WriteMode) -> HalResult<()> {
+        self.require_visa("Write mode")?;
         unsafe { hal_call!(HAL_SetSerialWriteMode(self.port, mode as i32)) }
     }
 
+    /// Not supported by `Backend::Os`.
     pub fn set_flow_control(&self, flow: FlowControl) -> HalResult<()> {
+        self.require_visa("Flow control")?;
         unsafe { hal_call!(HAL_SetSerialFlowControl(self.port, flow as i32)) }
     }
 
-    pub fn set_timeout(&self, timeout: f64) -> HalResult<()> {
-        unsafe { hal_call!(HAL_SetSerialTimeout(self.port, timeout)) }
+    /// Set how long reads and writes wait before timing out.
+    pub fn set_timeout(&self, timeout: Duration) -> HalResult<()> {
+        let timeout = timeout.as_secs() as f64 + timeout.subsec_nanos() as f64 / 1_000_000_000.0;
+        unsafe { serial_call!(self, HAL_SetSerialTimeout | HAL_SetOSSerialTimeout(timeout)) }
     }
 
+    /// Make reads return as soon as `terminator` is received. Not supported by `Backend::Os`.
     pub fn enable_termination(&self, terminator: u8) -> HalResult<()> {
+        self.require_visa("Termination")?;
         unsafe { hal_call!(HAL_EnableSerialTermination(self.port, terminator as c_char)) }
     }
 
+    /// Not supported by `Backend::Os`.
     pub fn disable_termination(&self) -> HalResult<()> {
+        self.require_visa("Termination")?;
         unsafe { hal_call!(HAL_DisableSerialTermination(self.port)) }
     }
 
+    /// Not supported by `Backend::Os`.
     pub fn set_read_buffer_size(&self, size: i32) -> HalResult<()> {
+        self.require_visa("Read buffer size")?;
         unsafe { hal_call!(HAL_SetSerialReadBufferSize(self.port, size)) }
     }
 
+    /// Not supported by `Backend::Os`.
     pub fn set_write_buffer_size(&self, size: i32) -> HalResult<()> {
+        self.require_visa("Write buffer size")?;
         unsafe { hal_call!(HAL_SetSerialWriteBufferSize(self.port, size)) }
     }
 
     pub fn get_serial_bytes_received(&self) -> HalResult<i32> {
-        unsafe { hal_call!(HAL_GetSerialBytesReceived(self.port)) }
+        unsafe { serial_call!(self, HAL_GetSerialBytesReceived | HAL_GetOSSerialBytesReceived()) }
+    }
+
+    /// Read directly from a port, bypassing the `BufRead` buffer. This doesn't take `self`
+    /// so that it can read into `self.buffer`.
+    fn read_port(port: Port, backend: Backend, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut status = 0;
+        let count = cmp::min(buffer.len(), i32::MAX as usize) as i32;
+        let read = unsafe {
+            match backend {
+                Backend::Visa => HAL_ReadSerial(port, buffer.as_mut_ptr() as *mut c_char, count, &mut status),
+                Backend::Os => HAL_ReadOSSerial(port, buffer.as_mut_ptr() as *mut c_char, count, &mut status),
+            }
+        };
+
+        transfer_result(read, status, !buffer.is_empty())
+    }
+
+    /// Flush the HAL's write buffer out to the port.
+    fn flush_port(&self) -> HalResult<()> {
+        unsafe { serial_call!(self, HAL_FlushSerial | HAL_FlushOSSerial()) }
     }
 
-    // pub fn HAL_ReadSerial(port: Port, buffer: *mut c_char, count: i32, status: *mut i32) -> i32;
-    pub fn read(&self, buffer: &mut [u8]) -> HalResult<i32> {
-        unsafe { hal_call!(HAL_ReadSerial(self.port, buffer.as_mut_ptr() as *mut c_char, buffer.len() as i32)) }
+    /// Discard everything in the receive buffers, including the one used by `BufRead`.
+    pub fn clear(&mut self) -> HalResult<()> {
+        self.position = 0;
+        self.filled = 0;
+        unsafe { serial_call!(self, HAL_ClearSerial | HAL_ClearOSSerial()) }
     }
 
-    // pub fn HAL_WriteSerial(port: Port, buffer: *const c_char, count: i32, status: *mut i32) -> i32;
-    pub fn write(&self, buffer: &[u8]) -> HalResult<i32> {
-        unsafe { hal_call!(HAL_WriteSerial(self.port, buffer.as_ptr() as *const c_char, buffer.len() as i32)) }
+    fn close_port(&mut self) -> HalResult<()> {
+        self.closed = true;
+        unsafe { serial_call!(self, HAL_CloseSerial | HAL_CloseOSSerial()) }
     }
 
-    pub fn flush(&self) -> HalResult<()> {
-        unsafe { hal_call!(HAL_FlushSerial(self.port)) }
+    /// Close the port, reporting any error. Dropping a `Serial` also closes it, but ignores
+    /// errors.
+    pub fn close(mut self) -> HalResult<()> {
+        self.close_port()
+    }
+}
+
+/// Turn the count and status of a serial transfer into an `io::Result`. Transfers that time
+/// out, or that move no data at all when some was requested, are reported as `TimedOut`.
+fn transfer_result(count: i32, status: i32, requested: bool) -> io::Result<usize> {
+    match status {
+        0 if count == 0 && requested => Err(io::Error::from(io::ErrorKind::TimedOut)),
+        0 => Ok(count as usize),
+        // Partial transfers still moved data, so report that instead of the timeout
+        VI_ERROR_TMO if count > 0 => Ok(count as usize),
+        VI_ERROR_TMO => Err(io::Error::from(io::ErrorKind::TimedOut)),
+        status => Err(io::Error::other(HalError::Hal(FfiError(status)))),
+    }
+}
+
+impl Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Skip our buffer entirely for big reads when it's empty, like `BufReader` does
+        if self.position == self.filled && buf.len() >= self.buffer.len() {
+            return Serial::read_port(self.port, self.backend, buf);
+        }
+
+        let read = {
+            let mut available = self.fill_buf()?;
+            available.read(buf)?
+        };
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Serial {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.filled {
+            // Reset first, so a failed read leaves the buffer empty rather than stale
+            self.position = 0;
+            self.filled = 0;
+            self.filled = Serial::read_port(self.port, self.backend, &mut self.buffer)?;
+        }
+
+        Ok(&self.buffer[self.position..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = cmp::min(self.position + amount, self.filled);
+    }
+}
+
+impl Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut status = 0;
+        let count = cmp::min(buf.len(), i32::MAX as usize) as i32;
+        let written = unsafe {
+            match self.backend {
+                Backend::Visa => HAL_WriteSerial(self.port, buf.as_ptr() as *const c_char, count, &mut status),
+                Backend::Os => HAL_WriteOSSerial(self.port, buf.as_ptr() as *const c_char, count, &mut status),
+            }
+        };
+
+        transfer_result(written, status, !buf.is_empty())
     }
 
-    pub fn clear(&self) -> HalResult<()> {
-        unsafe { hal_call!(HAL_ClearSerial(self.port)) }
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_port().map_err(io::Error::other)
     }
 }
 
 impl Drop for Serial {
     fn drop(&mut self) {
-        unsafe { hal_call!(HAL_CloseSerial(self.port)).unwrap() }
+        if !self.closed {
+            // There's nothing useful to do with an error here, use `close` to see it.
+            let _ = self.close_port();
+        }
     }
 }
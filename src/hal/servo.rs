@@ -0,0 +1,85 @@
+use error::*;
+use hal::motor_safety::{Actuator, StopAction};
+use hal::pwm::{PeriodScale, Pwm, PwmConfigMicros};
+use hal::usage_reporting::{self, ResourceType};
+
+/// Default pulse bounds for hobby servos, in microseconds
+const DEFAULT_MAX_SERVO_PWM: f64 = 2400.0;
+const DEFAULT_MIN_SERVO_PWM: f64 = 600.0;
+
+const DEFAULT_MAX_SERVO_ANGLE: f64 = 180.0;
+const DEFAULT_MIN_SERVO_ANGLE: f64 = 0.0;
+
+/// A hobby servo driven by a PWM port. Positions are mapped linearly from the minimum to the
+/// maximum angle, which default to 0 and 180 degrees.
+#[derive(Debug)]
+pub struct Servo {
+    pwm: Pwm,
+    min_angle: f64,
+    max_angle: f64,
+}
+
+impl Servo {
+    pub fn new(channel: i32) -> HalResult<Self> {
+        let pwm = Pwm::new(channel)?;
+        // Servos only care about the endpoints, so the center and deadband are left at zero
+        pwm.set_config_micros(PwmConfigMicros {
+            max: DEFAULT_MAX_SERVO_PWM,
+            deadband_max: 0.0,
+            center: 0.0,
+            deadband_min: 0.0,
+            min: DEFAULT_MIN_SERVO_PWM,
+        })?;
+        pwm.set_period_scale(PeriodScale::Scale4X)?;
+
+        usage_reporting::report(ResourceType::Servo, channel, 0, b"\0");
+
+        Ok(Servo { pwm, min_angle: DEFAULT_MIN_SERVO_ANGLE, max_angle: DEFAULT_MAX_SERVO_ANGLE })
+    }
+
+    pub fn pwm(&self) -> &Pwm { &self.pwm }
+
+    /// Set the angles, in degrees, that the servo reaches at the minimum and maximum pulse
+    /// widths. Panics if `min` is not less than `max`.
+    pub fn set_angle_range(&mut self, min: f64, max: f64) {
+        assert!(min < max, "Servo angle range is empty: min was {}, max was {}", min, max);
+        self.min_angle = min;
+        self.max_angle = max;
+    }
+
+    pub fn min_angle(&self) -> f64 { self.min_angle }
+
+    pub fn max_angle(&self) -> f64 { self.max_angle }
+
+    /// Set the position of the servo, from 0 (minimum angle) to 1 (maximum angle).
+    pub fn set(&self, position: f64) -> HalResult<()> {
+        self.pwm.set_position(position)
+    }
+
+    /// Get the commanded position, from 0 to 1.
+    pub fn get(&self) -> HalResult<f64> {
+        self.pwm.get_position()
+    }
+
+    /// Set the angle of the servo in degrees. Angles outside the configured range are clamped.
+    pub fn set_angle(&self, degrees: f64) -> HalResult<()> {
+        let degrees = degrees.max(self.min_angle).min(self.max_angle);
+        self.set((degrees - self.min_angle) / self.angle_range())
+    }
+
+    /// Get the commanded angle in degrees.
+    pub fn get_angle(&self) -> HalResult<f64> {
+        Ok(self.get()? * self.angle_range() + self.min_angle)
+    }
+
+    /// Stop sending pulses, letting the servo go limp.
+    pub fn set_disabled(&self) -> HalResult<()> {
+        self.pwm.set_disabled()
+    }
+
+    fn angle_range(&self) -> f64 { self.max_angle - self.min_angle }
+}
+
+impl Actuator for Servo {
+    fn stop_action(&self) -> StopAction { self.pwm.stop_action() }
+}
@@ -0,0 +1,259 @@
+//! Consistent views of everything the driver station sends in a packet.
+
+use hal::driverstation::{self, AllianceStation, ControlWord, MatchInfo};
+use hal::joystick::{Axes, Buttons, Joystick, Povs, MAX_JOYSTICKS};
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+
+/// The state of one joystick port. Ports with nothing plugged in have no axes, buttons or POVs.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct JoystickSnapshot {
+    pub axes: Axes,
+    pub buttons: Buttons,
+    pub povs: Povs,
+}
+
+/// Everything the driver station sent in a single packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DriverStationSnapshot {
+    /// Counts up by one for every packet seen by a `DriverStationMonitor`, and is 0 for
+    /// snapshots taken with `capture`.
+    pub sequence: usize,
+    pub control_word: ControlWord,
+    pub joysticks: [JoystickSnapshot; MAX_JOYSTICKS as usize],
+    pub alliance_station: Option<AllianceStation>,
+    pub match_time: f64,
+    pub match_info: Option<MatchInfo>,
+}
+
+impl DriverStationSnapshot {
+    /// Read the current driver station data. If a packet arrives while reading, the data is
+    /// read again, so everything in the snapshot comes from the same packet. Values that fail
+    /// to read are left at their defaults.
+    pub fn capture() -> Self {
+        loop {
+            // Goes through the packet count rather than `is_new_control_data`, which would
+            // hide this packet from other code on the thread
+            let before = driverstation::packets_seen();
+            let snapshot = Self::read();
+            if driverstation::packets_seen() == before {
+                return snapshot;
+            }
+        }
+    }
+
+    fn read() -> Self {
+        let mut joysticks = [JoystickSnapshot::default(); MAX_JOYSTICKS as usize];
+        for (port, snapshot) in joysticks.iter_mut().enumerate() {
+            let joystick = Joystick::new(port as i32).expect("joystick port out of range");
+            snapshot.axes = joystick.get_axes().unwrap_or_default();
+            snapshot.buttons = joystick.get_buttons().unwrap_or_default();
+            snapshot.povs = joystick.get_povs().unwrap_or_default();
+        }
+
+        DriverStationSnapshot {
+            sequence: 0,
+            control_word: driverstation::get_control_word().unwrap_or_default(),
+            joysticks,
+            alliance_station: driverstation::get_alliance_station(),
+            match_time: driverstation::match_time().unwrap_or(-1.0),
+            match_info: driverstation::get_match_info().ok(),
+        }
+    }
+
+    /// Get the state of a joystick port. Panics if `port` isn't between 0 and 5.
+    pub fn joystick(&self, port: usize) -> &JoystickSnapshot { &self.joysticks[port] }
+}
+
+// Holds the latest snapshot without a lock. Readers never wait: they announce themselves in the
+// reader count for the current epoch, clone the `Arc` and leave. Swapping in a new snapshot
+// moves readers over to the other count, and the old snapshot is only released once the count
+// it could have been read under drains. There is only ever one writer, the monitor thread.
+struct LatestCell {
+    current: AtomicPtr<DriverStationSnapshot>,
+    epoch: AtomicUsize,
+    readers: [AtomicUsize; 2],
+}
+
+impl LatestCell {
+    fn new(snapshot: DriverStationSnapshot) -> Self {
+        LatestCell {
+            current: AtomicPtr::new(Arc::into_raw(Arc::new(snapshot)) as *mut _),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+        }
+    }
+
+    fn load(&self) -> Arc<DriverStationSnapshot> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let readers = &self.readers[epoch % 2];
+            readers.fetch_add(1, Ordering::SeqCst);
+
+            // If a swap happened before we were counted, the writer may not be waiting for us
+            if self.epoch.load(Ordering::SeqCst) != epoch {
+                readers.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+
+            // The pointer can't be released while we're counted, so it's safe to add a
+            // reference through it
+            let current = ManuallyDrop::new(unsafe { Arc::from_raw(self.current.load(Ordering::SeqCst)) });
+            let latest = Arc::clone(&current);
+            readers.fetch_sub(1, Ordering::SeqCst);
+            return latest;
+        }
+    }
+
+    // Must only be called from one thread at a time
+    fn store(&self, snapshot: DriverStationSnapshot) {
+        let new = Arc::into_raw(Arc::new(snapshot)) as *mut _;
+        let old = self.current.swap(new, Ordering::SeqCst);
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst);
+
+        // Readers that start from now on are counted in the other epoch and see the new
+        // pointer, so this only waits for reads that were already in progress
+        while self.readers[epoch % 2].load(Ordering::SeqCst) != 0 {
+            thread::yield_now();
+        }
+
+        drop(unsafe { Arc::from_raw(old) });
+    }
+}
+
+impl Drop for LatestCell {
+    fn drop(&mut self) {
+        drop(unsafe { Arc::from_raw(*self.current.get_mut()) });
+    }
+}
+
+impl fmt::Debug for LatestCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LatestCell").field(&self.load()).finish()
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    latest: LatestCell,
+    sequence: AtomicUsize,
+    running: AtomicBool,
+}
+
+/// Keeps an up to date `DriverStationSnapshot` by capturing one in a background thread every
+/// time a driver station packet arrives.
+#[derive(Debug)]
+pub struct DriverStationMonitor {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DriverStationMonitor {
+    /// Start the background thread. The first snapshot is captured before this returns.
+    pub fn start() -> Self {
+        let shared = Arc::new(Shared {
+            latest: LatestCell::new(DriverStationSnapshot::capture()),
+            sequence: AtomicUsize::new(0),
+            running: AtomicBool::new(true),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("driver station monitor".into())
+            .spawn(move || {
+                while thread_shared.running.load(Ordering::SeqCst) {
+                    // Time out now and then so that the thread notices when it is stopped
+                    if !driverstation::wait_for_data(Some(0.1)) {
+                        continue;
+                    }
+
+                    let mut snapshot = DriverStationSnapshot::capture();
+                    snapshot.sequence = thread_shared.sequence.load(Ordering::SeqCst) + 1;
+                    let sequence = snapshot.sequence;
+                    thread_shared.latest.store(snapshot);
+                    thread_shared.sequence.store(sequence, Ordering::SeqCst);
+                }
+            })
+            .expect("failed to spawn driver station monitor thread");
+
+        DriverStationMonitor { shared, thread: Some(thread) }
+    }
+
+    /// Get the most recent snapshot. This never takes a lock or waits for the background
+    /// thread.
+    pub fn latest(&self) -> Arc<DriverStationSnapshot> { self.shared.latest.load() }
+
+    /// Get the sequence number of the most recent snapshot, to cheaply check if a new packet
+    /// has arrived.
+    pub fn sequence(&self) -> usize { self.shared.sequence.load(Ordering::SeqCst) }
+
+    /// Stop the background thread and wait for it to finish.
+    pub fn stop(mut self) { self.stop_thread(); }
+
+    fn stop_thread(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DriverStationMonitor {
+    fn drop(&mut self) { self.stop_thread(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(sequence: usize) -> DriverStationSnapshot {
+        DriverStationSnapshot {
+            sequence,
+            control_word: ControlWord::default(),
+            joysticks: [JoystickSnapshot::default(); MAX_JOYSTICKS as usize],
+            alliance_station: None,
+            match_time: -1.0,
+            match_info: None,
+        }
+    }
+
+    #[test]
+    fn latest_cell_releases_replaced_snapshots() {
+        let cell = LatestCell::new(snapshot(0));
+        let first = cell.load();
+        cell.store(snapshot(1));
+
+        assert_eq!(first.sequence, 0);
+        assert_eq!(Arc::strong_count(&first), 1);
+        assert_eq!(cell.load().sequence, 1);
+        assert_eq!(Arc::strong_count(&cell.load()), 2);
+    }
+
+    #[test]
+    fn latest_cell_readers_race_the_writer() {
+        let cell = Arc::new(LatestCell::new(snapshot(0)));
+        let readers = (0..4).map(|_| {
+            let cell = cell.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                while last < 2000 {
+                    let sequence = cell.load().sequence;
+                    assert!(sequence >= last);
+                    last = sequence;
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for sequence in 1..=2000 {
+            cell.store(snapshot(sequence));
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&cell.load()), 2);
+    }
+}
@@ -0,0 +1,137 @@
+use error::*;
+use hal::motor_safety::{Actuator, StopAction};
+use hal::pwm::{PeriodScale, Pwm, PwmConfigMicros};
+use hal::usage_reporting::{self, ResourceType};
+
+/// A motor controller that takes a speed between -1 and 1.
+pub trait SpeedController {
+    fn set(&self, speed: f64) -> HalResult<()>;
+
+    /// Get the most recently set speed, taking inversion into account.
+    fn get(&self) -> HalResult<f64>;
+
+    /// Invert the direction of the motor. Takes effect on the next call to `set`.
+    fn set_inverted(&mut self, inverted: bool);
+
+    fn is_inverted(&self) -> bool;
+
+    /// Stop driving the motor until the next call to `set`.
+    fn disable(&self) -> HalResult<()>;
+}
+
+/// The pulse configuration needed to drive a PWM motor controller.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PwmProfile {
+    pub config: PwmConfigMicros,
+    pub period_scale: PeriodScale,
+    pub eliminate_deadband: bool,
+}
+
+/// A motor controller driven by a PWM port. Use one of the vendor specific types like `Talon`
+/// unless the controller is not supported.
+#[derive(Debug)]
+pub struct PwmSpeedController {
+    pwm: Pwm,
+    inverted: bool,
+}
+
+impl PwmSpeedController {
+    /// Create a controller on `channel` with the given pulse profile, reporting it as
+    /// `resource`.
+    pub fn new(channel: i32, profile: &PwmProfile, resource: ResourceType) -> HalResult<Self> {
+        let pwm = Pwm::new(channel)?;
+        pwm.set_config_micros(profile.config)?;
+        pwm.set_period_scale(profile.period_scale)?;
+        pwm.set_eliminate_deadband(profile.eliminate_deadband)?;
+        pwm.set_speed(0.0)?;
+        pwm.latch_zero()?;
+
+        usage_reporting::report(resource, channel, 0, b"\0");
+
+        Ok(PwmSpeedController { pwm, inverted: false })
+    }
+
+    pub fn pwm(&self) -> &Pwm { &self.pwm }
+}
+
+impl SpeedController for PwmSpeedController {
+    fn set(&self, speed: f64) -> HalResult<()> {
+        self.pwm.set_speed(if self.inverted { -speed } else { speed })
+    }
+
+    fn get(&self) -> HalResult<f64> {
+        self.pwm.get_speed().map(|speed| if self.inverted { -speed } else { speed })
+    }
+
+    fn set_inverted(&mut self, inverted: bool) { self.inverted = inverted; }
+
+    fn is_inverted(&self) -> bool { self.inverted }
+
+    fn disable(&self) -> HalResult<()> { self.pwm.set_disabled() }
+}
+
+impl Actuator for PwmSpeedController {
+    fn stop_action(&self) -> StopAction { self.pwm.stop_action() }
+}
+
+macro_rules! pwm_speed_controllers {
+    ($($(#[$attr:meta])* $name:ident: $resource:ident, [$max:expr, $db_max:expr, $center:expr, $db_min:expr, $min:expr], $scale:ident;)*) => {
+        $(
+            $(#[$attr])*
+            #[derive(Debug)]
+            pub struct $name {
+                controller: PwmSpeedController,
+            }
+
+            impl $name {
+                pub const PROFILE: PwmProfile = PwmProfile {
+                    config: PwmConfigMicros {
+                        max: $max,
+                        deadband_max: $db_max,
+                        center: $center,
+                        deadband_min: $db_min,
+                        min: $min,
+                    },
+                    period_scale: PeriodScale::$scale,
+                    eliminate_deadband: false,
+                };
+
+                pub fn new(channel: i32) -> HalResult<Self> {
+                    PwmSpeedController::new(channel, &Self::PROFILE, ResourceType::$resource)
+                        .map(|controller| $name { controller })
+                }
+
+                pub fn pwm(&self) -> &Pwm { self.controller.pwm() }
+            }
+
+            impl SpeedController for $name {
+                fn set(&self, speed: f64) -> HalResult<()> { self.controller.set(speed) }
+                fn get(&self) -> HalResult<f64> { self.controller.get() }
+                fn set_inverted(&mut self, inverted: bool) { self.controller.set_inverted(inverted) }
+                fn is_inverted(&self) -> bool { self.controller.is_inverted() }
+                fn disable(&self) -> HalResult<()> { self.controller.disable() }
+            }
+
+            impl Actuator for $name {
+                fn stop_action(&self) -> StopAction { self.controller.stop_action() }
+            }
+        )*
+    }
+}
+
+// Bounds are in microseconds, and match the ones used by WPILibC
+pwm_speed_controllers! {
+    /// Cross The Road Electronics Talon and Talon SR
+    Talon: Talon, [2037.0, 1539.0, 1513.0, 1487.0, 989.0], Scale1X;
+    /// Vex Robotics Victor 888. Older Victors need a slower update rate, so every other pulse
+    /// is squelched.
+    Victor: Victor, [2027.0, 1525.0, 1507.0, 1490.0, 1026.0], Scale2X;
+    /// Vex Robotics Victor SP
+    VictorSP: VictorSP, [2004.0, 1520.0, 1500.0, 1480.0, 997.0], Scale1X;
+    /// REV Robotics SPARK
+    Spark: RevSPARK, [2003.0, 1550.0, 1500.0, 1460.0, 999.0], Scale1X;
+    /// Mindsensors SD540
+    SD540: MindsensorsSD540, [2050.0, 1550.0, 1500.0, 1440.0, 940.0], Scale1X;
+    /// Texas Instruments / Vex Robotics Jaguar, driven over PWM
+    Jaguar: Jaguar, [2310.0, 1550.0, 1507.0, 1454.0, 697.0], Scale1X;
+}
@@ -2,6 +2,9 @@ use std::os::raw::*;
 use error::*;
 use hal::types::*;
 use hal::analog_trigger::AnalogTriggerType;
+use hal::dio::{DigitalSource, SourceBorrow};
+use std::marker::PhantomData;
+use std::time::Duration;
 
 extern "C" {
     fn HAL_InitializeSPI(port: SpiPort, status: *mut i32);
@@ -147,6 +150,79 @@ impl Spi {
             Polarity::ActiveLow => unsafe { hal_call!(HAL_SetSPIChipSelectActiveLow(self.port)) },
         }
     }
+
+    /// Set up automatic transfers, with room to queue `buffer_size` received bytes. Automatic
+    /// transfers are stopped and freed when the returned `SpiAuto` is dropped.
+    pub fn init_auto<'s>(&self, buffer_size: i32) -> HalResult<SpiAuto<'_, 's>> {
+        unsafe { hal_call!(HAL_InitSPIAuto(self.port, buffer_size))?; }
+        Ok(SpiAuto { spi: self, _source: PhantomData })
+    }
+}
+
+/// Automatic transfers on a SPI port, done by the FPGA either periodically or whenever a
+/// digital source changes. The source that triggers transfers is borrowed for the lifetime
+/// `'s`.
+#[derive(Debug)]
+pub struct SpiAuto<'spi, 's> {
+    spi: &'spi Spi,
+    _source: SourceBorrow<'s>,
+}
+
+impl<'spi, 's> SpiAuto<'spi, 's> {
+    /// Start a transfer every `period`.
+    pub fn start_rate(&self, period: Duration) -> HalResult<()> {
+        let period = period.as_secs() as f64 + period.subsec_nanos() as f64 / 1e9;
+        unsafe { hal_call!(HAL_StartSPIAutoRate(self.spi.port, period as c_double)) }
+    }
+
+    /// Start a transfer on the chosen edges of `source`.
+    pub fn start_trigger(&self, source: &'s impl DigitalSource, rising: bool, falling: bool) -> HalResult<()> {
+        unsafe {
+            hal_call!(HAL_StartSPIAutoTrigger(self.spi.port, source.handle(), source.analog_trigger_type(),
+                                              rising as NativeBool, falling as NativeBool))
+        }
+    }
+
+    pub fn stop(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_StopSPIAuto(self.spi.port)) }
+    }
+
+    /// Set the bytes sent at the start of every transfer, followed by `zero_size` zero bytes.
+    pub fn set_transmit_data(&self, data: &[u8], zero_size: i32) -> HalResult<()> {
+        unsafe { hal_call!(HAL_SetSPIAutoTransmitData(self.spi.port, data.as_ptr(), data.len() as i32, zero_size)) }
+    }
+
+    /// Do a single transfer right away.
+    pub fn force_read(&self) -> HalResult<()> {
+        unsafe { hal_call!(HAL_ForceSPIAutoRead(self.spi.port)) }
+    }
+
+    /// Read received bytes into `buffer`, waiting at most `timeout` for it to fill. Returns the
+    /// number of bytes read. An empty `buffer` returns how many bytes are queued.
+    pub fn read_received_data(&self, buffer: &mut [u8], timeout: Duration) -> HalResult<usize> {
+        let timeout = timeout.as_secs() as f64 + timeout.subsec_nanos() as f64 / 1e9;
+        unsafe {
+            hal_call!(HAL_ReadSPIAutoReceivedData(self.spi.port, buffer.as_mut_ptr(), buffer.len() as i32,
+                                                  timeout as c_double))
+                .map(|read| read.max(0) as usize)
+        }
+    }
+
+    /// Get the number of received bytes dropped because the queue was full.
+    pub fn get_dropped_count(&self) -> HalResult<i32> {
+        unsafe { hal_call!(HAL_GetSPIAutoDroppedCount(self.spi.port)) }
+    }
+}
+
+impl<'spi, 's> Drop for SpiAuto<'spi, 's> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, but the status still has to be written somewhere
+        let mut status = 0;
+        unsafe {
+            HAL_StopSPIAuto(self.spi.port, &mut status);
+            HAL_FreeSPIAuto(self.spi.port, &mut status);
+        }
+    }
 }
 
 impl Read for Spi {
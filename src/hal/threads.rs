@@ -0,0 +1,123 @@
+use error::*;
+use hal::types::NativeBool;
+use std::os::unix::thread::{JoinHandleExt, RawPthread};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle, Thread};
+
+extern "C" {
+    fn HAL_GetThreadPriority(handle: *const RawPthread, is_real_time: *mut NativeBool, status: *mut i32) -> i32;
+    fn HAL_GetCurrentThreadPriority(is_real_time: *mut NativeBool, status: *mut i32) -> i32;
+    fn HAL_SetThreadPriority(handle: *const RawPthread, real_time: NativeBool, priority: i32, status: *mut i32) -> NativeBool;
+    fn HAL_SetCurrentThreadPriority(real_time: NativeBool, priority: i32, status: *mut i32) -> NativeBool;
+}
+
+const HAL_THREAD_PRIORITY_ERROR: i32 = -1152;
+
+/// The scheduling priority of a thread.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ThreadPriority {
+    /// Whether the thread uses the realtime (`SCHED_FIFO`) scheduler.
+    pub realtime: bool,
+    /// For realtime threads this is between 1 and 99, with 99 being the highest priority. The
+    /// HAL forces this to 0 for non-realtime threads.
+    pub priority: i32,
+}
+
+impl ThreadPriority {
+    /// A realtime priority. Panics if `priority` is not between 1 and 99.
+    pub fn realtime(priority: i32) -> Self {
+        require_value_between!(priority, 1, 99);
+        ThreadPriority { realtime: true, priority }
+    }
+
+    /// The default, non-realtime priority.
+    pub fn normal() -> Self {
+        ThreadPriority { realtime: false, priority: 0 }
+    }
+}
+
+fn check_set(succeeded: HalResult<NativeBool>) -> HalResult<()> {
+    match succeeded? {
+        0 => Err(HalError::Hal(FfiError(HAL_THREAD_PRIORITY_ERROR))),
+        _ => Ok(()),
+    }
+}
+
+pub fn get_thread_priority(handle: &impl JoinHandleExt) -> HalResult<ThreadPriority> {
+    let pthread = handle.as_pthread_t();
+    let mut realtime = 0;
+    let priority = unsafe { hal_call!(HAL_GetThreadPriority(&pthread, &mut realtime))? };
+    Ok(ThreadPriority { realtime: realtime != 0, priority })
+}
+
+pub fn get_current_thread_priority() -> HalResult<ThreadPriority> {
+    let mut realtime = 0;
+    let priority = unsafe { hal_call!(HAL_GetCurrentThreadPriority(&mut realtime))? };
+    Ok(ThreadPriority { realtime: realtime != 0, priority })
+}
+
+pub fn set_thread_priority(handle: &impl JoinHandleExt, priority: ThreadPriority) -> HalResult<()> {
+    let pthread = handle.as_pthread_t();
+    check_set(unsafe {
+        hal_call!(HAL_SetThreadPriority(&pthread, priority.realtime as NativeBool, priority.priority))
+    })
+}
+
+pub fn set_current_thread_priority(priority: ThreadPriority) -> HalResult<()> {
+    check_set(unsafe {
+        hal_call!(HAL_SetCurrentThreadPriority(priority.realtime as NativeBool, priority.priority))
+    })
+}
+
+/// Handle to a thread started with `spawn_realtime`.
+#[derive(Debug)]
+pub struct RealtimeJoinHandle<T> {
+    // `None` is only returned when setting the priority failed, in which case this handle is
+    // never given out.
+    handle: JoinHandle<Option<T>>,
+}
+
+impl<T> RealtimeJoinHandle<T> {
+    pub fn thread(&self) -> &Thread { self.handle.thread() }
+
+    pub fn join(self) -> thread::Result<T> {
+        self.handle.join().map(|result| result.expect("realtime thread ran without its priority set"))
+    }
+}
+
+impl<T> JoinHandleExt for RealtimeJoinHandle<T> {
+    fn as_pthread_t(&self) -> RawPthread { self.handle.as_pthread_t() }
+    fn into_pthread_t(self) -> RawPthread { self.handle.into_pthread_t() }
+}
+
+/// Spawn a thread that runs `func` with a realtime `priority`. The priority is set from inside
+/// the new thread before `func` is called. If that fails, the error is returned and `func` is
+/// never called.
+pub fn spawn_realtime<F, T>(priority: i32, func: F) -> HalResult<RealtimeJoinHandle<T>>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let priority = ThreadPriority::realtime(priority);
+    let (sender, receiver) = mpsc::channel();
+
+    let handle = thread::spawn(move || {
+        let result = set_current_thread_priority(priority);
+        let succeeded = result.is_ok();
+        // The receiver only goes away once we've sent something
+        let _ = sender.send(result);
+        if succeeded { Some(func()) } else { None }
+    });
+
+    match receiver.recv() {
+        Ok(Ok(())) => Ok(RealtimeJoinHandle { handle }),
+        Ok(Err(err)) => {
+            let _ = handle.join();
+            Err(err)
+        }
+        // The thread died before reporting back, which means it panicked
+        Err(_) => {
+            let _ = handle.join();
+            Err(HalError::Hal(FfiError(HAL_THREAD_PRIORITY_ERROR)))
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Time kept by the FPGA clock, which counts microseconds since the FPGA was last reset, and the
+//! monotonic clock CAN timestamps are based on.
+
+use error::*;
+use std::io;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::os::raw::{c_int, c_long};
+use std::time::Duration;
+
+extern "C" {
+    // From libc. CAN timestamps come from this clock rather than the FPGA
+    fn clock_gettime(clock: c_int, time: *mut Timespec) -> c_int;
+}
+
+const CLOCK_MONOTONIC: c_int = 1;
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: c_long,
+    tv_nsec: c_long,
+}
+
+/// Convert a `Duration` to whole microseconds, saturating at `u64::MAX`.
+pub(crate) fn micros(duration: Duration) -> u64 {
+    duration.as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(duration.subsec_micros() as u64)
+}
+
+/// A point in FPGA time, with microsecond resolution.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FpgaInstant(u64);
+
+impl FpgaInstant {
+    pub fn now() -> HalResult<Self> {
+        ::hal::get_fpga_time().map(FpgaInstant)
+    }
+
+    pub fn from_micros(micros: u64) -> Self { FpgaInstant(micros) }
+
+    pub fn as_micros(&self) -> u64 { self.0 }
+
+    /// Convert an interrupt timestamp. These are in seconds, but come from the 32 bit
+    /// microsecond FPGA counter, so they wrap around about every 71.6 minutes. The timestamp is
+    /// taken to be the latest time at or before `reference` that matches. Use
+    /// `from_interrupt_timestamp` to compare against the current time.
+    pub fn from_interrupt_timestamp_before(seconds: f64, reference: FpgaInstant) -> Self {
+        let counter = (seconds.max(0.0) * 1e6).round() as u64 as u32;
+        let behind = (reference.0 as u32).wrapping_sub(counter) as u64;
+        FpgaInstant(reference.0.saturating_sub(behind))
+    }
+
+    /// Convert an interrupt timestamp from an interrupt that has already happened.
+    pub fn from_interrupt_timestamp(seconds: f64) -> HalResult<Self> {
+        FpgaInstant::now().map(|now| FpgaInstant::from_interrupt_timestamp_before(seconds, now))
+    }
+
+    /// Get the time from `earlier` to this instant, or `None` if `earlier` is later.
+    pub fn checked_duration_since(&self, earlier: FpgaInstant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_micros)
+    }
+
+    /// Get the time from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: FpgaInstant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Get the time that has passed since this instant.
+    pub fn elapsed(&self) -> HalResult<Duration> {
+        FpgaInstant::now().map(|now| now.duration_since(*self))
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<FpgaInstant> {
+        self.0.checked_add(micros(duration)).map(FpgaInstant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<FpgaInstant> {
+        self.0.checked_sub(micros(duration)).map(FpgaInstant)
+    }
+}
+
+impl Add<Duration> for FpgaInstant {
+    type Output = FpgaInstant;
+    fn add(self, duration: Duration) -> FpgaInstant {
+        self.checked_add(duration).expect("overflow when adding duration to FPGA instant")
+    }
+}
+
+impl AddAssign<Duration> for FpgaInstant {
+    fn add_assign(&mut self, duration: Duration) { *self = *self + duration; }
+}
+
+impl Sub<Duration> for FpgaInstant {
+    type Output = FpgaInstant;
+    fn sub(self, duration: Duration) -> FpgaInstant {
+        self.checked_sub(duration).expect("overflow when subtracting duration from FPGA instant")
+    }
+}
+
+impl SubAssign<Duration> for FpgaInstant {
+    fn sub_assign(&mut self, duration: Duration) { *self = *self - duration; }
+}
+
+impl Sub<FpgaInstant> for FpgaInstant {
+    type Output = Duration;
+    fn sub(self, earlier: FpgaInstant) -> Duration { self.duration_since(earlier) }
+}
+
+/// The time a CAN message was received. This is not FPGA time: the HAL documents the CAN receive
+/// timestamp as "based off of CLOCK_MONOTONIC" (`HAL_CAN_ReceiveMessage` in `hal/CAN.h`), in
+/// milliseconds cut down to 32 bits. The monotonic clock and the FPGA counter don't share a
+/// base, so a CAN timestamp can only be compared with other CAN timestamps or with
+/// `CanTimestamp::now`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Default)]
+pub struct CanTimestamp(u32);
+
+impl CanTimestamp {
+    /// Read the monotonic clock, cut down the same way CAN timestamps are.
+    pub fn now() -> HalResult<Self> {
+        let mut time = Timespec { tv_sec: 0, tv_nsec: 0 };
+        if unsafe { clock_gettime(CLOCK_MONOTONIC, &mut time) } != 0 {
+            return Err(HalError::Other(Box::new(io::Error::last_os_error())));
+        }
+
+        let millis = (time.tv_sec as u64).wrapping_mul(1_000)
+            .wrapping_add(time.tv_nsec as u64 / 1_000_000);
+        Ok(CanTimestamp(millis as u32))
+    }
+
+    pub fn from_millis(millis: u32) -> Self { CanTimestamp(millis) }
+
+    pub fn as_millis(&self) -> u32 { self.0 }
+
+    /// Get the time from `earlier` to this timestamp. The timestamps wrap around about every
+    /// 49.7 days, so this is only meaningful if `earlier` was less than that long before.
+    pub fn wrapping_duration_since(&self, earlier: CanTimestamp) -> Duration {
+        Duration::from_millis(self.0.wrapping_sub(earlier.0) as u64)
+    }
+
+    /// Get the time that has passed since this timestamp.
+    pub fn elapsed(&self) -> HalResult<Duration> {
+        CanTimestamp::now().map(|now| now.wrapping_duration_since(*self))
+    }
+}
+
+/// A timer that can be started and stopped, adding up the time it spends running. Works like
+/// the `Timer` from WPILib.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Default)]
+pub struct Timer {
+    // Time accumulated before the last start
+    accumulated: Duration,
+    started: Option<FpgaInstant>,
+}
+
+impl Timer {
+    /// Create a stopped timer at zero.
+    pub fn new() -> Self { Timer::default() }
+
+    /// Get the total time the timer has been running since the last reset.
+    pub fn get(&self) -> HalResult<Duration> {
+        match self.started {
+            Some(started) => Ok(self.accumulated + started.elapsed()?),
+            None => Ok(self.accumulated),
+        }
+    }
+
+    pub fn is_running(&self) -> bool { self.started.is_some() }
+
+    /// Start the timer. Does nothing if it is already running.
+    pub fn start(&mut self) -> HalResult<()> {
+        if self.started.is_none() {
+            self.started = Some(FpgaInstant::now()?);
+        }
+        Ok(())
+    }
+
+    /// Stop the timer, keeping the time it has run so far.
+    pub fn stop(&mut self) -> HalResult<()> {
+        self.accumulated = self.get()?;
+        self.started = None;
+        Ok(())
+    }
+
+    /// Set the time back to zero. A running timer keeps running.
+    pub fn reset(&mut self) -> HalResult<()> {
+        self.accumulated = Duration::default();
+        if self.started.is_some() {
+            self.started = Some(FpgaInstant::now()?);
+        }
+        Ok(())
+    }
+
+    /// Check whether at least `period` has passed on the timer.
+    pub fn has_elapsed(&self, period: Duration) -> HalResult<bool> {
+        self.get().map(|time| time >= period)
+    }
+
+    /// If at least `period` has passed, take `period` off the timer and return `true`. Since
+    /// the leftover time is kept, calling this in a loop triggers on average once every
+    /// `period` without drifting.
+    pub fn advance_if_elapsed(&mut self, period: Duration) -> HalResult<bool> {
+        if !self.has_elapsed(period)? {
+            return Ok(false);
+        }
+
+        if period <= self.accumulated {
+            self.accumulated -= period;
+        } else {
+            let rest = period - self.accumulated;
+            self.accumulated = Duration::default();
+            if let Some(ref mut started) = self.started {
+                *started += rest;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Measures the time since it was created or last restarted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Stopwatch {
+    started: FpgaInstant,
+}
+
+impl Stopwatch {
+    pub fn start() -> HalResult<Self> {
+        FpgaInstant::now().map(|started| Stopwatch { started })
+    }
+
+    pub fn started(&self) -> FpgaInstant { self.started }
+
+    pub fn elapsed(&self) -> HalResult<Duration> { self.started.elapsed() }
+
+    /// Start over from zero, returning the time measured up to now.
+    pub fn restart(&mut self) -> HalResult<Duration> {
+        let now = FpgaInstant::now()?;
+        let elapsed = now.duration_since(self.started);
+        self.started = now;
+        Ok(elapsed)
+    }
+}
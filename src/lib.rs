@@ -12,7 +12,7 @@ extern crate backtrace;
 
 macro_rules! require_value_between {
     ($val:expr, $low:expr, $high:expr) => {
-        if $val > $high || $val < $low {
+        if !($low..=$high).contains(&$val) {
             panic!("Value out of bounds. Range is {} to {}, but the actual value was {}", $low, $high, $val);
         }
     };